@@ -0,0 +1,50 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use randomart::{render::{render_colours, Canvas}, utils::{fnv1a, Colour}, vm::Program, Grammar, Node};
+
+const SEED: &str = "spiderman";
+const DEPTH: u32 = 40;
+const SIZE: u32 = 256;
+
+fn generate(c: &mut Criterion) {
+    c.bench_function("gen_rule depth 40", |b| {
+        b.iter(|| Grammar::default(fnv1a(SEED)).gen_rule(0, black_box(DEPTH)))
+    });
+}
+
+// every pixel of `canvas` through `pixel`, the way `render_colours` walks it
+fn render_with(canvas: &Canvas, pixel: impl Fn(f32, f32) -> Colour) -> Vec<Colour> {
+    (0..canvas.height)
+        .flat_map(|py| (0..canvas.width).map(move |px| (px, py)))
+        .map(|(px, py)| {
+            let coordinates = canvas.coordinates(px, py);
+            pixel(coordinates.x, coordinates.y)
+        })
+        .collect()
+}
+
+fn eval_rgb(c: &mut Criterion) {
+    let node = Grammar::default(fnv1a(SEED)).gen_rule(0, DEPTH).unwrap();
+    let Node::Triple(r, g, b) = &*node else {
+        panic!("the Entry rule makes a Triple");
+    };
+    let canvas = Canvas::new(SIZE, SIZE);
+    let mut group = c.benchmark_group("render 256x256");
+    group.sample_size(10);
+    group.bench_function("eval_rgb", |b| {
+        b.iter(|| render_colours(black_box(&node), &canvas))
+    });
+
+    let (cr, cg, cb) = (r.compile(), g.compile(), b.compile());
+    group.bench_function("compile", |b| {
+        b.iter(|| render_with(&canvas, |x, y| Colour { r: cr(x, y), g: cg(x, y), b: cb(x, y) }))
+    });
+
+    let (pr, pg, pb) = (Program::compile(r), Program::compile(g), Program::compile(b));
+    group.bench_function("vm", |b| {
+        b.iter(|| render_with(&canvas, |x, y| Colour { r: pr.eval(x, y), g: pg.eval(x, y), b: pb.eval(x, y) }))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, generate, eval_rgb);
+criterion_main!(benches);
@@ -0,0 +1,335 @@
+// versioned binary encoding of a grammar's rules and seed:
+//
+//   magic "RAGR" | version: u8 | seed: u64 | rule count: u32
+//   per rule:      alternate count: u32
+//   per alternate: probability: f32 | min depth: u32 (since version 2) | node
+//   node:          tag: u8 | payload (Rule: u32, Number: f32, Boolean: u8, Wave kind: u8, Turbulence octaves: u8,
+//                  Noise seed: u32, Palette: colour count u32 then r, g, b: f32 per colour) | children, in order
+//
+// integers and floats are little-endian. tags are append-only and older versions are still
+// read, so old files stay readable
+use crate::{Grammar, GrammarBranches, GrammarError, Node, WaveKind};
+use crate::utils::{Colour, LinearCongruentialGenerator, Rng};
+
+const MAGIC: &[u8; 4] = b"RAGR";
+const VERSION: u8 = 2;
+// nodes nest at most this deep when decoding, so a hostile payload is an error rather than a
+// stack overflow. `Reader::node` frames run to several KB in debug builds, so this stays well
+// inside a 2 MB thread stack; grammar alternates are a handful of levels deep
+const MAX_NESTING: usize = 64;
+
+fn node_tag(node: &Node) -> u8 {
+    match node {
+        Node::X => 0,
+        Node::Y => 1,
+        Node::Random => 2,
+        Node::Rule(_) => 3,
+        Node::Number(_) => 4,
+        Node::Boolean(_) => 5,
+        Node::Sqrt(_) => 6,
+        Node::Sin(_) => 7,
+        Node::Cos(_) => 8,
+        Node::Exp(_) => 9,
+        Node::Sigmoid(_) => 10,
+        Node::Add(_, _) => 11,
+        Node::Mult(_, _) => 12,
+        Node::Div(_, _) => 13,
+        Node::Modulo(_, _) => 14,
+        Node::Gt(_, _) => 15,
+        Node::Triple(_, _, _) => 16,
+        Node::If(_, _, _) => 17,
+        Node::Mix(_, _, _, _) => 18,
+        Node::MixUnbounded(_, _, _, _) => 19,
+        Node::Remap(_, _, _, _, _) => 20,
+        Node::Smoothmin(_, _, _) => 21,
+        Node::Wrap(_) => 22,
+        Node::Wave(_, _, _, _) => 23,
+        Node::Turbulence(_, _, _) => 24,
+        Node::Palette(_, _) => 25,
+        Node::Sign(_) => 26,
+        Node::Step(_, _) => 27,
+        Node::Pow(_, _) => 28,
+        Node::Tan(_) => 29,
+        Node::Abs(_) => 30,
+        Node::Sub(_, _) => 31,
+        Node::Min(_, _) => 32,
+        Node::Max(_, _) => 33,
+        Node::Log(_) => 34,
+        Node::Floor(_) => 35,
+        Node::Ceil(_) => 36,
+        Node::Round(_) => 37,
+        Node::Clamp(_, _, _) => 38,
+        Node::Smoothstep(_, _, _) => 39,
+        Node::Lerp(_, _, _) => 40,
+        Node::Radius => 41,
+        Node::Fract(_) => 42,
+        Node::Noise(_, _, _) => 43,
+        Node::Quad(_, _, _, _) => 44,
+    }
+}
+
+fn write_node(node: &Node, out: &mut Vec<u8>) {
+    out.push(node_tag(node));
+    match node {
+        Node::Rule(index) => out.extend_from_slice(&(*index as u32).to_le_bytes()),
+        Node::Number(value) => out.extend_from_slice(&value.to_le_bytes()),
+        Node::Boolean(value) => out.push(*value as u8),
+        Node::Wave(_, _, _, kind) => out.push(*kind as u8),
+        Node::Turbulence(_, _, octaves) => out.push(*octaves),
+        Node::Noise(_, _, seed) => out.extend_from_slice(&seed.to_le_bytes()),
+        Node::Palette(_, colours) => {
+            out.extend_from_slice(&(colours.len() as u32).to_le_bytes());
+            for colour in colours {
+                for channel in [colour.r, colour.g, colour.b] {
+                    out.extend_from_slice(&channel.to_le_bytes());
+                }
+            }
+        }
+        _ => {}
+    }
+    for child in node.children() {
+        write_node(child, out);
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    depth: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], GrammarError> {
+        if self.bytes.len() < N {
+            return Err(GrammarError::UnexpectedEnd);
+        }
+        let (head, rest) = self.bytes.split_at(N);
+        self.bytes = rest;
+        Ok(head.try_into().expect("split_at returned N bytes"))
+    }
+
+    fn u8(&mut self) -> Result<u8, GrammarError> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, GrammarError> {
+        Ok(u32::from_le_bytes(self.take()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, GrammarError> {
+        Ok(u64::from_le_bytes(self.take()?))
+    }
+
+    fn f32(&mut self) -> Result<f32, GrammarError> {
+        Ok(f32::from_le_bytes(self.take()?))
+    }
+
+    fn child(&mut self) -> Result<Box<Node>, GrammarError> {
+        if self.depth == MAX_NESTING {
+            return Err(GrammarError::NestingTooDeep(MAX_NESTING));
+        }
+        self.depth += 1;
+        let child = self.node();
+        self.depth -= 1;
+        Ok(Box::new(child?))
+    }
+
+    fn node(&mut self) -> Result<Node, GrammarError> {
+        let tag = self.u8()?;
+        let node = match tag {
+            0 => Node::X,
+            1 => Node::Y,
+            2 => Node::Random,
+            3 => Node::Rule(self.u32()? as usize),
+            4 => Node::Number(self.f32()?),
+            5 => Node::Boolean(self.u8()? != 0),
+            6 => Node::Sqrt(self.child()?),
+            7 => Node::Sin(self.child()?),
+            8 => Node::Cos(self.child()?),
+            9 => Node::Exp(self.child()?),
+            10 => Node::Sigmoid(self.child()?),
+            11 => Node::Add(self.child()?, self.child()?),
+            12 => Node::Mult(self.child()?, self.child()?),
+            13 => Node::Div(self.child()?, self.child()?),
+            14 => Node::Modulo(self.child()?, self.child()?),
+            15 => Node::Gt(self.child()?, self.child()?),
+            16 => Node::Triple(self.child()?, self.child()?, self.child()?),
+            17 => Node::If(self.child()?, self.child()?, self.child()?),
+            18 => Node::Mix(self.child()?, self.child()?, self.child()?, self.child()?),
+            19 => Node::MixUnbounded(self.child()?, self.child()?, self.child()?, self.child()?),
+            20 => Node::Remap(self.child()?, self.child()?, self.child()?, self.child()?, self.child()?),
+            21 => Node::Smoothmin(self.child()?, self.child()?, self.child()?),
+            22 => Node::Wrap(self.child()?),
+            23 => {
+                let kind = match self.u8()? {
+                    0 => WaveKind::Sin,
+                    1 => WaveKind::Cos,
+                    2 => WaveKind::Tan,
+                    kind => return Err(GrammarError::UnknownWaveKind(kind)),
+                };
+                Node::Wave(self.child()?, self.child()?, self.child()?, kind)
+            }
+            24 => {
+                let octaves = self.u8()?;
+                Node::Turbulence(self.child()?, self.child()?, octaves)
+            }
+            25 => {
+                let mut colours = Vec::new();
+                for _ in 0..self.u32()? {
+                    colours.push(Colour { r: self.f32()?, g: self.f32()?, b: self.f32()? });
+                }
+                Node::Palette(self.child()?, colours)
+            }
+            26 => Node::Sign(self.child()?),
+            27 => Node::Step(self.child()?, self.child()?),
+            28 => Node::Pow(self.child()?, self.child()?),
+            29 => Node::Tan(self.child()?),
+            30 => Node::Abs(self.child()?),
+            31 => Node::Sub(self.child()?, self.child()?),
+            32 => Node::Min(self.child()?, self.child()?),
+            33 => Node::Max(self.child()?, self.child()?),
+            34 => Node::Log(self.child()?),
+            35 => Node::Floor(self.child()?),
+            36 => Node::Ceil(self.child()?),
+            37 => Node::Round(self.child()?),
+            38 => Node::Clamp(self.child()?, self.child()?, self.child()?),
+            39 => Node::Smoothstep(self.child()?, self.child()?, self.child()?),
+            40 => Node::Lerp(self.child()?, self.child()?, self.child()?),
+            41 => Node::Radius,
+            42 => Node::Fract(self.child()?),
+            43 => {
+                let seed = self.u32()?;
+                Node::Noise(self.child()?, self.child()?, seed)
+            }
+            44 => Node::Quad(self.child()?, self.child()?, self.child()?, self.child()?),
+            tag => return Err(GrammarError::UnknownNodeTag(tag)),
+        };
+        Ok(node)
+    }
+}
+
+impl<R: Rng> Grammar<R> {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(MAGIC);
+        out.push(VERSION);
+        out.extend_from_slice(&self.seed.to_le_bytes());
+        out.extend_from_slice(&(self.rules.len() as u32).to_le_bytes());
+        for rule in &self.rules {
+            out.extend_from_slice(&(rule.alternates.len() as u32).to_le_bytes());
+            for branch in &rule.alternates {
+                out.extend_from_slice(&branch.probability.to_le_bytes());
+                out.extend_from_slice(&branch.min_depth.to_le_bytes());
+                write_node(&branch.node, &mut out);
+            }
+        }
+        out
+    }
+}
+
+impl Grammar {
+    // the rng starts over from the stored seed, its position is not saved
+    pub fn from_bytes(bytes: &[u8]) -> Result<Grammar, GrammarError> {
+        let mut reader = Reader { bytes, depth: 0 };
+        if reader.take::<4>().ok().as_ref() != Some(MAGIC) {
+            return Err(GrammarError::BadMagic);
+        }
+        let version = reader.u8()?;
+        if version == 0 || version > VERSION {
+            return Err(GrammarError::UnsupportedVersion(version));
+        }
+
+        let seed = reader.u64()?;
+        let rule_count = reader.u32()?;
+        let mut rules = Vec::new();
+        for _ in 0..rule_count {
+            let mut branches = GrammarBranches::new();
+            for _ in 0..reader.u32()? {
+                let probability = reader.f32()?;
+                let min_depth = if version >= 2 { reader.u32()? } else { 0 };
+                branches.add_gated_alternate(reader.node()?, probability, min_depth);
+            }
+            rules.push(branches);
+        }
+        if !reader.bytes.is_empty() {
+            return Err(GrammarError::TrailingBytes(reader.bytes.len()));
+        }
+
+        Ok(Grammar { rules, rng: LinearCongruentialGenerator::new(seed), seed })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::fnv1a;
+
+    #[test]
+    fn test_bytes_round_trip() {
+        let mut grammar = Grammar::default(fnv1a("bytes"));
+        let mut decoded = Grammar::from_bytes(&grammar.to_bytes()).unwrap();
+
+        assert_eq!(decoded.seed(), grammar.seed());
+        assert_eq!(decoded.to_bytes(), grammar.to_bytes());
+        assert_eq!(decoded.gen_rule(0, 12), grammar.gen_rule(0, 12));
+    }
+
+    #[test]
+    fn test_bytes_keeps_min_depth_and_reads_version_1() {
+        let mut grammar = Grammar::default(fnv1a("gated"));
+        grammar.rules[1].alternates[3].min_depth = 6;
+        let decoded = Grammar::from_bytes(&grammar.to_bytes()).unwrap();
+        assert_eq!(decoded.rules[1].alternates[3].min_depth, 6);
+
+        // version 1 had no min depth field: drop it from every alternate of an ungated grammar
+        let ungated = Grammar::default(fnv1a("gated")).to_bytes();
+        let mut version_1 = ungated[..17].to_vec();
+        version_1[4] = 1;
+        let mut reader = Reader { bytes: &ungated[17..], depth: 0 };
+        for _ in 0..3 {
+            let count = reader.u32().unwrap();
+            version_1.extend_from_slice(&count.to_le_bytes());
+            for _ in 0..count {
+                version_1.extend_from_slice(&reader.f32().unwrap().to_le_bytes());
+                assert_eq!(reader.u32().unwrap(), 0);
+                write_node(&reader.node().unwrap(), &mut version_1);
+            }
+        }
+        assert_eq!(Grammar::from_bytes(&version_1).unwrap().to_bytes(), ungated);
+    }
+
+    #[test]
+    fn test_bytes_rejects_bad_header() {
+        let mut bytes = Grammar::default(0).to_bytes();
+        bytes[4] = VERSION + 1;
+        assert_eq!(Grammar::from_bytes(&bytes).err(), Some(GrammarError::UnsupportedVersion(VERSION + 1)));
+
+        bytes[0] = b'X';
+        assert_eq!(Grammar::from_bytes(&bytes).err(), Some(GrammarError::BadMagic));
+        assert_eq!(Grammar::from_bytes(b"RA").err(), Some(GrammarError::BadMagic));
+
+        let truncated = Grammar::default(0).to_bytes();
+        assert_eq!(Grammar::from_bytes(&truncated[..truncated.len() - 1]).err(), Some(GrammarError::UnexpectedEnd));
+    }
+
+    #[test]
+    fn test_bytes_rejects_deep_nesting() {
+        let nested = |depth: usize| {
+            let mut bytes = MAGIC.to_vec();
+            bytes.push(VERSION);
+            bytes.extend_from_slice(&0u64.to_le_bytes());
+            bytes.extend_from_slice(&1u32.to_le_bytes());
+            bytes.extend_from_slice(&1u32.to_le_bytes());
+            bytes.extend_from_slice(&1.0f32.to_le_bytes());
+            bytes.extend_from_slice(&0u32.to_le_bytes());
+            bytes.extend(std::iter::repeat_n(6, depth));
+            bytes.push(0);
+            bytes
+        };
+
+        let decoded = Grammar::from_bytes(&nested(MAX_NESTING)).unwrap();
+        assert_eq!(decoded.to_bytes(), nested(MAX_NESTING));
+        assert_eq!(Grammar::from_bytes(&nested(MAX_NESTING + 1)).err(), Some(GrammarError::NestingTooDeep(MAX_NESTING)));
+        // deep enough to overflow the stack if decoding recursed without a limit
+        assert_eq!(Grammar::from_bytes(&nested(1 << 20)).err(), Some(GrammarError::NestingTooDeep(MAX_NESTING)));
+    }
+}
@@ -0,0 +1,124 @@
+// a tree turned into nested closures once up front, so evaluating a pixel no longer matches on
+// every node. each closure computes exactly what `eval` computes for its node, using the same
+// guarded helpers, so the results are bit for bit the same
+use crate::{clamp, div, ln, max, min, mix, mix_unbounded, modulo, pow, remap, smoothmin, smoothstep, tan, Node, MAX_TURBULENCE_OCTAVES};
+use crate::utils::{seeded_value_noise, turbulence};
+
+type Compiled = Box<dyn Fn(f32, f32) -> f32>;
+
+fn unary(inner: &Node, op: impl Fn(f32) -> f32 + 'static) -> Compiled {
+    let inner = inner.compile();
+    Box::new(move |x, y| op(inner(x, y)))
+}
+
+fn binary(lhs: &Node, rhs: &Node, op: impl Fn(f32, f32) -> f32 + 'static) -> Compiled {
+    let (lhs, rhs) = (lhs.compile(), rhs.compile());
+    Box::new(move |x, y| op(lhs(x, y), rhs(x, y)))
+}
+
+fn ternary(a: &Node, b: &Node, c: &Node, op: impl Fn(f32, f32, f32) -> f32 + 'static) -> Compiled {
+    let (a, b, c) = (a.compile(), b.compile(), c.compile());
+    Box::new(move |x, y| op(a(x, y), b(x, y), c(x, y)))
+}
+
+fn quaternary(a: &Node, b: &Node, c: &Node, d: &Node, op: impl Fn(f32, f32, f32, f32) -> f32 + 'static) -> Compiled {
+    let (a, b, c, d) = (a.compile(), b.compile(), c.compile(), d.compile());
+    Box::new(move |x, y| op(a(x, y), b(x, y), c(x, y), d(x, y)))
+}
+
+impl Node {
+    // panics on nodes `eval` panics on (Random, Rule, Triple, Quad, Palette), only at compile time
+    pub fn compile(&self) -> Box<dyn Fn(f32, f32) -> f32> {
+        match self {
+            Node::X => Box::new(|x, _| x),
+            Node::Y => Box::new(|_, y| y),
+            Node::Radius => Box::new(|x, y| (x * x + y * y).sqrt()),
+            Node::Number(value) => {
+                let value = *value;
+                Box::new(move |_, _| value)
+            }
+            Node::Boolean(value) => {
+                let value = if *value { 1.0 } else { 0.0 };
+                Box::new(move |_, _| value)
+            }
+            Node::Add(lhs, rhs) => binary(lhs, rhs, |lhs, rhs| (lhs + rhs)/2.0),
+            Node::Sub(lhs, rhs) => binary(lhs, rhs, |lhs, rhs| lhs - rhs),
+            Node::Min(lhs, rhs) => binary(lhs, rhs, min),
+            Node::Max(lhs, rhs) => binary(lhs, rhs, max),
+            Node::Mult(lhs, rhs) => binary(lhs, rhs, |lhs, rhs| lhs * rhs),
+            Node::Div(lhs, rhs) => binary(lhs, rhs, div),
+            Node::Modulo(lhs, rhs) => binary(lhs, rhs, modulo),
+            Node::Pow(base, exponent) => binary(base, exponent, pow),
+            Node::Gt(lhs, rhs) => binary(lhs, rhs, |lhs, rhs| if lhs - rhs > 0.0 { 1.0 } else { 0.0 }),
+            Node::Step(edge, value) => binary(edge, value, |edge, value| if value >= edge { 1.0 } else { 0.0 }),
+            Node::Sin(inner) => unary(inner, f32::sin),
+            Node::Cos(inner) => unary(inner, f32::cos),
+            Node::Exp(inner) => unary(inner, f32::exp),
+            Node::Sigmoid(inner) => unary(inner, |val| 1.0 / (1.0 + (-val).exp())),
+            Node::Tan(inner) => unary(inner, tan),
+            Node::Abs(inner) => unary(inner, f32::abs),
+            Node::Log(inner) => unary(inner, ln),
+            Node::Floor(inner) => unary(inner, f32::floor),
+            Node::Ceil(inner) => unary(inner, f32::ceil),
+            Node::Round(inner) => unary(inner, f32::round),
+            Node::Fract(inner) => unary(inner, |val| val - val.floor()),
+            Node::Sqrt(inner) => unary(inner, |val| val.sqrt().max(0.0)),
+            Node::Sign(inner) => unary(inner, |val| if val > 0.0 { 1.0 } else if val < 0.0 { -1.0 } else { 0.0 }),
+            Node::Wrap(inner) => unary(inner, |val| (2.0 * std::f32::consts::PI * (val - val.floor())).sin()),
+            Node::Clamp(value, lo, hi) => ternary(value, lo, hi, clamp),
+            Node::Smoothstep(edge0, edge1, value) => ternary(edge0, edge1, value, smoothstep),
+            Node::Lerp(a, b, t) => ternary(a, b, t, |a, b, t| a + (b - a) * t),
+            Node::Smoothmin(a, b, k) => ternary(a, b, k, smoothmin),
+            Node::Wave(input, frequency, phase, kind) => {
+                let kind = *kind;
+                ternary(input, frequency, phase, move |input, frequency, phase| kind.apply(frequency * input + phase))
+            }
+            Node::Mix(a, b, c, d) => quaternary(a, b, c, d, mix),
+            Node::MixUnbounded(a, b, c, d) => quaternary(a, b, c, d, mix_unbounded),
+            Node::Remap(value, in_lo, in_hi, out_lo, out_hi) => {
+                let (value, in_lo, in_hi) = (value.compile(), in_lo.compile(), in_hi.compile());
+                let (out_lo, out_hi) = (out_lo.compile(), out_hi.compile());
+                Box::new(move |x, y| remap(value(x, y), in_lo(x, y), in_hi(x, y), out_lo(x, y), out_hi(x, y)))
+            }
+            Node::Turbulence(x_node, y_node, octaves) => {
+                let octaves = (*octaves).min(MAX_TURBULENCE_OCTAVES);
+                binary(x_node, y_node, move |x, y| turbulence(x, y, octaves))
+            }
+            Node::Noise(x_node, y_node, seed) => {
+                let seed = *seed;
+                binary(x_node, y_node, move |x, y| seeded_value_noise(x, y, seed))
+            }
+            // only the taken branch is evaluated, as in `eval`
+            Node::If(cond, then, elze) => {
+                let (cond, then, elze) = (cond.compile(), then.compile(), elze.compile());
+                Box::new(move |x, y| if cond(x, y) > 0.0 { then(x, y) } else { elze(x, y) })
+            }
+            Node::Random | Node::Rule(_) | Node::Triple(_, _, _) | Node::Quad(_, _, _, _) | Node::Palette(_, _) => {
+                panic!("unexpected Node kind during compile: {:?}", self)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Grammar, Node};
+    use crate::utils::{fnv1a, LinearCongruentialGenerator};
+
+    #[test]
+    fn test_compile_matches_eval() {
+        let mut points = LinearCongruentialGenerator::new(fnv1a("sample points"));
+        for name in ["compile", "closures", "spiderman"] {
+            let Node::Triple(r, g, b) = *Grammar::default(fnv1a(name)).gen_rule(0, 20).unwrap() else {
+                panic!("the Entry rule makes a Triple");
+            };
+            for channel in [r, g, b] {
+                let compiled = channel.compile();
+                for _ in 0..1000 {
+                    let (x, y) = (points.next_float() * 2.0 - 1.0, points.next_float() * 2.0 - 1.0);
+                    assert_eq!(compiled(x, y).to_bits(), channel.eval(x, y).to_bits(), "{:?} at ({}, {})", channel, x, y);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,278 @@
+// parses a tree back from its derived `Debug` text, e.g. `Add(Sin(X), Number(-0.5))`:
+//
+//   term: word | word(term, ...) | word { field: term, ... } | [term, ...]
+//
+// words are runs of letters, digits and `_ . + -`, which covers op names as well as float
+// literals like `-1e-7`, `NaN` and `inf`. the terms are rewritten into the json tree that
+// `Node::from_json` builds, so both formats share one constructor and the same errors. payloads
+// follow the children, as `Debug` prints them: Wave kind, Turbulence octaves, Noise seed and
+// Palette's list of `Colour { r, g, b }`. `If { cond, then, elze }` is taken as `If(cond, then, elze)`
+use std::str::FromStr;
+use crate::{Node, ParseError};
+use crate::json::{self, Json};
+
+enum Body {
+    Bare,
+    Call(Vec<Term>),
+    Struct { open: usize, fields: Vec<(String, Term)> },
+    List(Vec<Term>),
+}
+
+struct Term {
+    position: usize,
+    word: String,
+    body: Body,
+}
+
+struct Tokenizer<'a> {
+    text: &'a str,
+    position: usize,
+}
+
+impl<'a> Tokenizer<'a> {
+    fn skip_whitespace(&mut self) {
+        let rest = &self.text[self.position..];
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    fn peek(&mut self) -> Result<char, ParseError> {
+        self.skip_whitespace();
+        self.text[self.position..].chars().next().ok_or(ParseError::UnexpectedEnd)
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), ParseError> {
+        let found = self.peek()?;
+        if found != expected {
+            return Err(ParseError::UnexpectedChar { position: self.position, found });
+        }
+        self.position += expected.len_utf8();
+        Ok(())
+    }
+
+    fn word(&mut self) -> Result<String, ParseError> {
+        let found = self.peek()?;
+        let rest = &self.text[self.position..];
+        let end = rest.find(|c: char| !(c.is_ascii_alphanumeric() || "_.+-".contains(c))).unwrap_or(rest.len());
+        if end == 0 {
+            return Err(ParseError::UnexpectedChar { position: self.position, found });
+        }
+        self.position += end;
+        Ok(rest[..end].to_string())
+    }
+
+    // `item` separated by commas up to `close`, the opening bracket already consumed
+    fn list<T>(&mut self, close: char, mut item: impl FnMut(&mut Self) -> Result<T, ParseError>) -> Result<Vec<T>, ParseError> {
+        let mut items = Vec::new();
+        if self.peek()? == close {
+            self.position += 1;
+            return Ok(items);
+        }
+        loop {
+            items.push(item(self)?);
+            match self.peek()? {
+                ',' => self.position += 1,
+                found if found == close => {
+                    self.position += 1;
+                    return Ok(items);
+                }
+                found => return Err(ParseError::UnexpectedChar { position: self.position, found }),
+            }
+        }
+    }
+
+    fn term(&mut self) -> Result<Term, ParseError> {
+        if self.peek()? == '[' {
+            let position = self.position;
+            self.position += 1;
+            let items = self.list(']', Self::term)?;
+            return Ok(Term { position, word: String::new(), body: Body::List(items) });
+        }
+
+        let position = self.position;
+        let word = self.word()?;
+        let body = match self.peek() {
+            Ok('(') => {
+                self.position += 1;
+                Body::Call(self.list(')', Self::term)?)
+            }
+            Ok('{') => {
+                let open = self.position;
+                self.position += 1;
+                let fields = self.list('}', |tokenizer| {
+                    let name = tokenizer.word()?;
+                    tokenizer.expect(':')?;
+                    Ok((name, tokenizer.term()?))
+                })?;
+                Body::Struct { open, fields }
+            }
+            _ => Body::Bare,
+        };
+        Ok(Term { position, word, body })
+    }
+}
+
+// the json key for a payload that `Debug` prints as the last argument
+fn payload_key(op: &str) -> Option<&'static str> {
+    match op {
+        "Rule" => Some("index"),
+        "Number" | "Boolean" => Some("value"),
+        "Wave" => Some("kind"),
+        "Turbulence" => Some("octaves"),
+        "Noise" => Some("seed"),
+        "Palette" => Some("colours"),
+        _ => None,
+    }
+}
+
+fn field<'a>(fields: &'a [(String, Term)], op: &str, name: &'static str) -> Result<&'a Term, ParseError> {
+    fields.iter()
+        .find(|(key, _)| key == name)
+        .map(|(_, term)| term)
+        .ok_or_else(|| ParseError::MissingField { op: op.to_string(), field: name })
+}
+
+fn to_payload(term: &Term, op: &str, key: &'static str) -> Result<Json, ParseError> {
+    let invalid = || ParseError::InvalidValue { op: op.to_string(), field: key };
+    match (&term.body, key) {
+        (Body::List(items), "colours") => items.iter()
+            .map(|item| match &item.body {
+                Body::Struct { fields, .. } if item.word == "Colour" => ["r", "g", "b"].into_iter()
+                    .map(|channel| Ok(Json::Number(field(fields, "Colour", channel)?.word.clone())))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map(Json::Array),
+                _ => Err(invalid()),
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(Json::Array),
+        (Body::Bare, "kind") => Ok(Json::String(term.word.clone())),
+        (Body::Bare, _) => Ok(match term.word.as_str() {
+            "true" => Json::Bool(true),
+            "false" => Json::Bool(false),
+            word => Json::Number(word.to_string()),
+        }),
+        _ => Err(invalid()),
+    }
+}
+
+fn to_json(term: &Term) -> Result<Json, ParseError> {
+    let op = term.word.as_str();
+    let mut args: Vec<&Term> = match &term.body {
+        Body::Bare => Vec::new(),
+        Body::Call(args) => args.iter().collect(),
+        Body::Struct { fields, .. } if op == "If" => ["cond", "then", "elze"].into_iter()
+            .map(|name| field(fields, op, name))
+            .collect::<Result<_, _>>()?,
+        Body::Struct { open, .. } => return Err(ParseError::UnexpectedChar { position: *open, found: '{' }),
+        Body::List(_) => return Err(ParseError::UnexpectedChar { position: term.position, found: '[' }),
+    };
+
+    let mut fields = vec![("op".to_string(), Json::String(op.to_string()))];
+    if let Some(key) = payload_key(op) {
+        if let Some(payload) = args.pop() {
+            fields.push((key.to_string(), to_payload(payload, op, key)?));
+        }
+    }
+    if !args.is_empty() {
+        let args = args.into_iter().map(to_json).collect::<Result<Vec<_>, _>>()?;
+        fields.push(("args".to_string(), Json::Array(args)));
+    }
+    Ok(Json::Object(fields))
+}
+
+fn parse_term(text: &str) -> Result<Term, ParseError> {
+    let mut tokenizer = Tokenizer { text, position: 0 };
+    let term = tokenizer.term()?;
+    tokenizer.skip_whitespace();
+    if tokenizer.position != text.len() {
+        return Err(ParseError::TrailingCharacters(tokenizer.position));
+    }
+    Ok(term)
+}
+
+// rewrites every bare word in node position (payloads are left alone) that names one of `rules`
+// into `Rule(index)`. any other bare word but the leaves X, Y, Radius and Random is an unknown rule
+fn resolve_rules(term: &mut Term, rules: &[&str]) -> Result<(), ParseError> {
+    let payloads = usize::from(payload_key(&term.word).is_some());
+    match &mut term.body {
+        Body::Bare => {
+            if let Some(index) = rules.iter().position(|rule| *rule == term.word) {
+                let index = Term { position: term.position, word: index.to_string(), body: Body::Bare };
+                term.word = "Rule".to_string();
+                term.body = Body::Call(vec![index]);
+            } else if !matches!(term.word.as_str(), "X" | "Y" | "Radius" | "Random") {
+                return Err(ParseError::UnknownRule(term.word.clone()));
+            }
+        }
+        Body::Call(args) => {
+            let children = args.len().saturating_sub(payloads);
+            for arg in &mut args[..children] {
+                resolve_rules(arg, rules)?;
+            }
+        }
+        Body::Struct { fields, .. } => {
+            for (_, field) in fields {
+                resolve_rules(field, rules)?;
+            }
+        }
+        Body::List(_) => {}
+    }
+    Ok(())
+}
+
+// a node in `Debug` syntax where a bare word naming one of `rules` stands for `Rule(index)`, as
+// grammar files write alternates
+pub(crate) fn parse_with_rules(text: &str, rules: &[&str]) -> Result<Node, ParseError> {
+    let mut term = parse_term(text)?;
+    resolve_rules(&mut term, rules)?;
+    json::to_node(&to_json(&term)?)
+}
+
+impl FromStr for Node {
+    type Err = ParseError;
+
+    fn from_str(text: &str) -> Result<Node, ParseError> {
+        json::to_node(&to_json(&parse_term(text)?)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Grammar, WaveKind};
+    use crate::utils::{fnv1a, Colour};
+
+    #[test]
+    fn test_debug_round_trip() {
+        for name in ["debug", "round", "trip", "spiderman"] {
+            let tree = Grammar::default(fnv1a(name)).gen_rule(0, 14).unwrap();
+            assert_eq!(format!("{:?}", tree).parse::<Node>(), Ok(*tree));
+        }
+
+        let exotic = Node::Palette(
+            Box::new(Node::Turbulence(
+                Box::new(Node::Wave(Box::new(Node::X), Box::new(Node::Rule(2)), Box::new(Node::Number(f32::NEG_INFINITY)), WaveKind::Tan)),
+                Box::new(Node::Noise(Box::new(Node::Boolean(true)), Box::new(Node::Number(-1e-7)), 4_000_000_000)),
+                5,
+            )),
+            vec![Colour { r: 0.25, g: -1.0, b: 0.1 }],
+        );
+        assert_eq!(Node::from_str(&format!("{:?}", exotic)), Ok(exotic));
+        assert_eq!(Node::from_str(" Add( Sin(X) ,Number(-0.5)) "), Ok(Node::Add(Box::new(Node::Sin(Box::new(Node::X))), Box::new(Node::Number(-0.5)))));
+        assert_eq!(
+            Node::from_str("If { cond: Gt(X, Y), then: X, elze: Y }"),
+            Ok(Node::If(Box::new(Node::Gt(Box::new(Node::X), Box::new(Node::Y))), Box::new(Node::X), Box::new(Node::Y)))
+        );
+        assert!(Node::from_str("Number(NaN)").is_ok_and(|node| matches!(node, Node::Number(value) if value.is_nan())));
+    }
+
+    #[test]
+    fn test_debug_parse_rejects_malformed_input() {
+        assert_eq!(Node::from_str("Add(X, "), Err(ParseError::UnexpectedEnd));
+        assert_eq!(Node::from_str("Add(X Y)"), Err(ParseError::UnexpectedChar { position: 6, found: 'Y' }));
+        assert_eq!(Node::from_str("X)"), Err(ParseError::TrailingCharacters(1)));
+        assert_eq!(Node::from_str("Blur(X)"), Err(ParseError::UnknownOp("Blur".to_string())));
+        assert_eq!(Node::from_str("Sin(X, Y)"), Err(ParseError::WrongArity { op: "Sin".to_string(), expected: 1, found: 2 }));
+        assert_eq!(Node::from_str("Number(lots)"), Err(ParseError::InvalidValue { op: "Number".to_string(), field: "value" }));
+        assert_eq!(Node::from_str("If { cond: X, then: Y }"), Err(ParseError::MissingField { op: "If".to_string(), field: "elze" }));
+    }
+}
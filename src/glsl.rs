@@ -0,0 +1,213 @@
+// GLSL export of a generated tree, so a render can run on the GPU and still match the CPU
+// reference. X and Y read `uv.x` and `uv.y`. wherever `eval` guards a value (division by ~0, the
+// log of ~0, NaN-propagating min/max, ...) the expression calls one of the `ra_` functions in
+// `HELPERS` instead of inlining the guard, so no operand is written (or evaluated) twice and
+// deep trees stay linear in size. paste `HELPERS` above the function that uses the expression.
+// needs GLSL 3.30 or later, for unsigned integers and `uintBitsToFloat`
+use crate::{Node, WaveKind};
+
+pub const HELPERS: &str = r#"float ra_div(float a, float b) { return abs(b) > 1e-6 ? a / b : 0.0; }
+float ra_mod(float a, float b) { return abs(b) > 1e-6 ? a - b * trunc(a / b) : 0.0; }
+float ra_min(float a, float b) { return isnan(a) || isnan(b) ? a + b : min(a, b); }
+float ra_max(float a, float b) { return isnan(a) || isnan(b) ? a + b : max(a, b); }
+float ra_tan(float v) { return abs(cos(v)) > 1e-6 ? tan(v) : 0.0; }
+float ra_log(float v) { return v > 1e-6 ? log(v) : 0.0; }
+float ra_round(float v) { return sign(v) * floor(abs(v) + 0.5); }
+float ra_sign(float v) { return v > 0.0 ? 1.0 : (v < 0.0 ? -1.0 : 0.0); }
+float ra_lerp(float a, float b, float t) { return a + (b - a) * t; }
+float ra_clamp(float v, float lo, float hi) {
+    return isnan(v) || isnan(lo) || isnan(hi) ? v + lo + hi : clamp(v, min(lo, hi), max(lo, hi));
+}
+float ra_smoothstep(float edge0, float edge1, float v) {
+    float range = edge1 - edge0;
+    if (abs(range) <= 1e-6) return 0.0;
+    float t = clamp((v - edge0) / range, 0.0, 1.0);
+    return t * t * (3.0 - 2.0 * t);
+}
+float ra_pow(float base, float exponent) {
+    if (fract(exponent) == 0.0 && exponent >= 1.0 && exponent <= 64.0) {
+        float product = base;
+        for (int i = 1; i < int(exponent); i++) product *= base;
+        return product;
+    }
+    if (base < 0.0 && fract(exponent) != 0.0) return 0.0;
+    if (exponent == 0.0) return 1.0;
+    if (base < 0.0) return (mod(exponent, 2.0) == 1.0 ? -1.0 : 1.0) * pow(-base, exponent);
+    return pow(base, exponent);
+}
+float ra_mix(float a, float b, float c, float d) {
+    a += 1.0; b += 1.0; c += 1.0; d += 1.0;
+    return (a * c + b * d) / max(a + b, 1e-6) - 1.0;
+}
+float ra_mix_unbounded(float a, float b, float c, float d) { return (a * c + b * d) / (a + b + 1e-6); }
+float ra_remap(float v, float in_lo, float in_hi, float out_lo, float out_hi) {
+    float in_range = in_hi - in_lo;
+    return abs(in_range) > 1e-6 ? out_lo + (v - in_lo) * (out_hi - out_lo) / in_range : 0.0;
+}
+float ra_smoothmin(float a, float b, float k) {
+    k = abs(k);
+    if (k <= 1e-6) return min(a, b);
+    float h = max(k - abs(a - b), 0.0) / k;
+    return min(a, b) - h * h * k * 0.25;
+}
+float ra_hash2d(int ix, int iy, uint seed) {
+    uint hash = (uint(ix) * 0x8da6b343u) ^ (uint(iy) * 0xd8163841u) ^ (seed * 0xcb1ab31fu);
+    hash = (hash ^ (hash >> 13u)) * 0x5bd1e995u;
+    hash ^= hash >> 15u;
+    return float(hash) / 4294967295.0 * 2.0 - 1.0;
+}
+float ra_noise(float x, float y, uint seed) {
+    vec2 cell = floor(vec2(x, y));
+    ivec2 i = ivec2(cell);
+    vec2 t = vec2(x, y) - cell;
+    t = t * t * (3.0 - 2.0 * t);
+    float top = ra_lerp(ra_hash2d(i.x, i.y, seed), ra_hash2d(i.x + 1, i.y, seed), t.x);
+    float bottom = ra_lerp(ra_hash2d(i.x, i.y + 1, seed), ra_hash2d(i.x + 1, i.y + 1, seed), t.x);
+    return ra_lerp(top, bottom, t.y);
+}
+float ra_turbulence(float x, float y, int octaves) {
+    float sum = 0.0, total = 0.0, frequency = 1.0, amplitude = 1.0;
+    for (int i = 0; i < octaves; i++) {
+        sum += amplitude * ra_noise(x * frequency, y * frequency, 0u);
+        total += amplitude;
+        frequency *= 2.0;
+        amplitude *= 0.5;
+    }
+    return total > 0.0 ? sum / total : 0.0;
+}
+float ra_palette_slot(float key, int count) {
+    return clamp(floor((key + 1.0) / 2.0 * float(count)), 0.0, float(count - 1));
+}
+"#;
+
+// a float literal GLSL accepts. it has no NaN or infinity literals, so those are spelled as bits
+fn float(value: f32) -> String {
+    if value.is_finite() {
+        format!("{:?}", value)
+    } else {
+        format!("uintBitsToFloat(0x{:08x}u)", value.to_bits())
+    }
+}
+
+impl Node {
+    // one channel as a GLSL float expression over `vec2 uv`, evaluating like `eval`
+    pub fn to_glsl(&self) -> String {
+        // the children, in order, as the arguments of a builtin or helper
+        let call = |name: &str| {
+            let args: Vec<String> = self.children().into_iter().map(Node::to_glsl).collect();
+            format!("{}({})", name, args.join(", "))
+        };
+
+        match self {
+            Node::X => "uv.x".to_string(),
+            Node::Y => "uv.y".to_string(),
+            Node::Radius => "sqrt(uv.x * uv.x + uv.y * uv.y)".to_string(),
+            Node::Number(value) => float(*value),
+            Node::Boolean(value) => if *value { "1.0" } else { "0.0" }.to_string(),
+            Node::Add(lhs, rhs) => format!("(({} + {}) / 2.0)", lhs.to_glsl(), rhs.to_glsl()),
+            Node::Sub(lhs, rhs) => format!("({} - {})", lhs.to_glsl(), rhs.to_glsl()),
+            Node::Mult(lhs, rhs) => format!("({} * {})", lhs.to_glsl(), rhs.to_glsl()),
+            Node::Div(_, _) => call("ra_div"),
+            Node::Modulo(_, _) => call("ra_mod"),
+            Node::Min(_, _) => call("ra_min"),
+            Node::Max(_, _) => call("ra_max"),
+            Node::Pow(_, _) => call("ra_pow"),
+            Node::Gt(lhs, rhs) => format!("(({} - {}) > 0.0 ? 1.0 : 0.0)", lhs.to_glsl(), rhs.to_glsl()),
+            Node::Step(edge, value) => format!("({} >= {} ? 1.0 : 0.0)", value.to_glsl(), edge.to_glsl()),
+            Node::Sin(_) => call("sin"),
+            Node::Cos(_) => call("cos"),
+            Node::Exp(_) => call("exp"),
+            Node::Abs(_) => call("abs"),
+            Node::Floor(_) => call("floor"),
+            Node::Ceil(_) => call("ceil"),
+            Node::Fract(_) => call("fract"),
+            Node::Tan(_) => call("ra_tan"),
+            Node::Log(_) => call("ra_log"),
+            Node::Round(_) => call("ra_round"),
+            Node::Sign(_) => call("ra_sign"),
+            Node::Sqrt(inner) => format!("sqrt(max({}, 0.0))", inner.to_glsl()),
+            Node::Sigmoid(inner) => format!("(1.0 / (1.0 + exp(-({}))))", inner.to_glsl()),
+            Node::Wrap(inner) => format!("sin(6.2831855 * fract({}))", inner.to_glsl()),
+            Node::Clamp(_, _, _) => call("ra_clamp"),
+            Node::Smoothstep(_, _, _) => call("ra_smoothstep"),
+            Node::Lerp(_, _, _) => call("ra_lerp"),
+            Node::Smoothmin(_, _, _) => call("ra_smoothmin"),
+            Node::Mix(_, _, _, _) => call("ra_mix"),
+            Node::MixUnbounded(_, _, _, _) => call("ra_mix_unbounded"),
+            Node::Remap(_, _, _, _, _) => call("ra_remap"),
+            Node::If(cond, then, elze) => format!("({} > 0.0 ? {} : {})", cond.to_glsl(), then.to_glsl(), elze.to_glsl()),
+            Node::Wave(input, frequency, phase, kind) => {
+                let function = match kind {
+                    WaveKind::Sin => "sin",
+                    WaveKind::Cos => "cos",
+                    WaveKind::Tan => "ra_tan",
+                };
+                format!("{}({} * {} + {})", function, frequency.to_glsl(), input.to_glsl(), phase.to_glsl())
+            }
+            Node::Turbulence(x, y, octaves) => {
+                format!("ra_turbulence({}, {}, {})", x.to_glsl(), y.to_glsl(), (*octaves).min(crate::MAX_TURBULENCE_OCTAVES))
+            }
+            Node::Noise(x, y, seed) => format!("ra_noise({}, {}, {}u)", x.to_glsl(), y.to_glsl(), seed),
+            Node::Random | Node::Rule(_) => {
+                panic!("{:?} only appears in grammars, generate a tree before exporting it", self)
+            }
+            Node::Triple(_, _, _) | Node::Quad(_, _, _, _) | Node::Palette(_, _) => {
+                panic!("{} is a whole colour, see `to_glsl_rgb`", self.kind_name())
+            }
+        }
+    }
+
+    // the colour of an Entry rule node as a GLSL `vec3` expression, matching `eval_rgb`
+    pub fn to_glsl_rgb(&self) -> String {
+        match self {
+            Node::Triple(first, second, third) => {
+                format!("vec3({}, {}, {})", first.to_glsl(), second.to_glsl(), third.to_glsl())
+            }
+            Node::Palette(key, colours) if !colours.is_empty() => {
+                let colours: Vec<String> = colours.iter()
+                    .map(|c| format!("vec3({}, {}, {})", float(c.r), float(c.g), float(c.b)))
+                    .collect();
+                format!(
+                    "vec3[{}]({})[int(ra_palette_slot({}, {}))]",
+                    colours.len(), colours.join(", "), key.to_glsl(), colours.len()
+                )
+            }
+            _ => "vec3(0.0)".to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::Colour;
+
+    #[test]
+    fn test_glsl_snapshot() {
+        let tree = Node::Triple(
+            Box::new(Node::Sqrt(Box::new(Node::Div(Box::new(Node::X), Box::new(Node::Number(-0.5)))))),
+            Box::new(Node::Add(Box::new(Node::Sin(Box::new(Node::Y))), Box::new(Node::Number(f32::INFINITY)))),
+            Box::new(Node::If(
+                Box::new(Node::Gt(Box::new(Node::X), Box::new(Node::Y))),
+                Box::new(Node::Modulo(Box::new(Node::Radius), Box::new(Node::Number(0.25)))),
+                Box::new(Node::Noise(Box::new(Node::X), Box::new(Node::Y), 7)),
+            )),
+        );
+        assert_eq!(
+            tree.to_glsl_rgb(),
+            "vec3(sqrt(max(ra_div(uv.x, -0.5), 0.0)), \
+             ((sin(uv.y) + uintBitsToFloat(0x7f800000u)) / 2.0), \
+             (((uv.x - uv.y) > 0.0 ? 1.0 : 0.0) > 0.0 ? ra_mod(sqrt(uv.x * uv.x + uv.y * uv.y), 0.25) : ra_noise(uv.x, uv.y, 7u)))"
+        );
+
+        let palette = Node::Palette(Box::new(Node::X), vec![Colour { r: 1.0, g: 0.0, b: 0.0 }, Colour { r: 0.0, g: 0.0, b: 1.0 }]);
+        assert_eq!(palette.to_glsl_rgb(), "vec3[2](vec3(1.0, 0.0, 0.0), vec3(0.0, 0.0, 1.0))[int(ra_palette_slot(uv.x, 2))]");
+
+        let wave = Node::Wave(Box::new(Node::X), Box::new(Node::Number(2.0)), Box::new(Node::Y), WaveKind::Tan);
+        assert_eq!(wave.to_glsl(), "ra_tan(2.0 * uv.x + uv.y)");
+
+        for helper in ["ra_div", "ra_mod", "ra_noise", "ra_palette_slot"] {
+            assert!(HELPERS.contains(&format!("float {}(", helper)));
+        }
+    }
+}
@@ -0,0 +1,113 @@
+// a line-based text format for grammars, one rule per line:
+//
+//   C ::= 1/2 Add(C, C) | 0.25 Sin(C) | 0.25 A
+//
+// each alternate is a probability (a decimal or a fraction like `6/36`) and a node in `Debug`
+// syntax (see `debug.rs`), where a bare rule name stands for `Rule(index)`. rules are numbered in
+// the order they are defined, so the first one is the entry rule, and may be used before their
+// line. blank lines and lines starting with `#` are skipped
+use crate::{debug, GrammarBranches, GrammarParseError, ParseError};
+
+// how far a rule's probabilities may sum from 1, enough for decimals written to a few places
+const SUM_TOLERANCE: f32 = 1e-4;
+
+fn probability(text: &str) -> Option<f32> {
+    let value = match text.split_once('/') {
+        Some((numerator, denominator)) => numerator.parse::<f32>().ok()? / denominator.parse::<f32>().ok()?,
+        None => text.parse().ok()?,
+    };
+    (value.is_finite() && value >= 0.0).then_some(value)
+}
+
+pub fn parse(src: &str) -> Result<Vec<GrammarBranches>, GrammarParseError> {
+    // (line number, name, alternates) of every rule, names first so rules can refer ahead
+    let mut definitions = Vec::new();
+    for (index, text) in src.lines().enumerate() {
+        let line = index + 1;
+        let text = text.trim();
+        if text.is_empty() || text.starts_with('#') {
+            continue;
+        }
+        let Some((name, alternates)) = text.split_once("::=") else {
+            return Err(GrammarParseError::MissingDefinition { line });
+        };
+        let name = name.trim();
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(GrammarParseError::MissingDefinition { line });
+        }
+        if definitions.iter().any(|(_, defined, _)| *defined == name) {
+            return Err(GrammarParseError::DuplicateRule { line, name: name.to_string() });
+        }
+        definitions.push((line, name, alternates));
+    }
+    if definitions.is_empty() {
+        return Err(GrammarParseError::NoRules);
+    }
+
+    let names: Vec<&str> = definitions.iter().map(|(_, name, _)| *name).collect();
+    let mut rules = Vec::new();
+    for (line, name, alternates) in definitions {
+        let mut branches = GrammarBranches::new();
+        for alternate in alternates.split('|') {
+            let alternate = alternate.trim();
+            let (weight, node) = alternate.split_once(char::is_whitespace).unwrap_or((alternate, ""));
+            let weight = probability(weight).ok_or_else(|| GrammarParseError::InvalidProbability { line, text: weight.to_string() })?;
+            let node = debug::parse_with_rules(node.trim(), &names).map_err(|error| match error {
+                ParseError::UnknownRule(name) => GrammarParseError::UnknownRule { line, name },
+                error => GrammarParseError::InvalidNode { line, error },
+            })?;
+            branches.add_alternate(node, weight);
+        }
+
+        let sum: f32 = branches.alternates.iter().map(|branch| branch.probability).sum();
+        if (sum - 1.0).abs() > SUM_TOLERANCE {
+            return Err(GrammarParseError::ProbabilitySum { line, rule: name.to_string(), sum });
+        }
+        rules.push(branches);
+    }
+    Ok(rules)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::fnv1a;
+    use crate::{Grammar, Node};
+
+    const DEFAULT: &str = "
+        # the default grammar
+        E ::= 1 Triple(C, C, C)
+        C ::= 6/36 A | 1/36 Add(C, C) | 1/36 Mult(C, C) | 3/36 Sin(C) | 3/36 Cos(C) | 1/36 Exp(C) | 1/36 Sqrt(C) | 1/36 Div(C, C) | 1/36 MixUnbounded(C, C, C, C) | 1/36 Sigmoid(C) | 1/36 Tan(C) | 1/36 Abs(C) | 1/36 Sub(C, C) | 1/36 Min(C, C) | 1/36 Max(C, C) | 1/36 Pow(C, C) | 1/36 Log(C) | 1/36 Floor(C) | 1/36 Ceil(C) | 1/36 Round(C) | 1/36 Clamp(C, C, C) | 1/36 Sign(C) | 1/36 Smoothstep(C, C, C) | 1/36 Lerp(C, C, C) | 1/36 Fract(C) | 1/36 Step(C, C) | 1/36 Noise(C, C, 0)
+        A ::= 1/4 X | 1/4 Y | 1/4 Radius | 1/4 Random
+    ";
+
+    #[test]
+    fn test_parse_the_default_grammar() {
+        let rules = parse(DEFAULT).unwrap();
+        assert_eq!(rules.len(), 3);
+        assert_eq!(*rules[0].alternates[0].node, Node::Triple(Box::new(Node::Rule(1)), Box::new(Node::Rule(1)), Box::new(Node::Rule(1))));
+
+        for name in ["dsl", "spiderman"] {
+            let parsed = Grammar::build(rules.clone(), fnv1a(name)).gen_rule(0, 20);
+            assert!(parsed.is_some());
+            assert_eq!(parsed, Grammar::default(fnv1a(name)).gen_rule(0, 20));
+        }
+    }
+
+    #[test]
+    fn test_parse_reports_bad_rules() {
+        assert_eq!(parse("E ::= 1 Sin(D)").err(), Some(GrammarParseError::UnknownRule { line: 1, name: "D".to_string() }));
+        assert_eq!(
+            parse("E ::= 1 A\n\nA ::= 0.5 X | 0.25 Y").err(),
+            Some(GrammarParseError::ProbabilitySum { line: 3, rule: "A".to_string(), sum: 0.75 })
+        );
+        assert_eq!(parse("E ::= half X").err(), Some(GrammarParseError::InvalidProbability { line: 1, text: "half".to_string() }));
+        assert_eq!(parse("E = 1 X").err(), Some(GrammarParseError::MissingDefinition { line: 1 }));
+        assert_eq!(parse("E ::= 1 X\nE ::= 1 Y").err(), Some(GrammarParseError::DuplicateRule { line: 2, name: "E".to_string() }));
+        assert_eq!(
+            parse("E ::= 1 Sin(X, Y)").err(),
+            Some(GrammarParseError::InvalidNode { line: 1, error: ParseError::WrongArity { op: "Sin".to_string(), expected: 1, found: 2 } })
+        );
+        assert_eq!(parse("# nothing\n").err(), Some(GrammarParseError::NoRules));
+    }
+}
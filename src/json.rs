@@ -0,0 +1,369 @@
+// hand-written JSON encoding of a tree, so interop does not need serde:
+//
+//   {"op": "Add", "args": [{"op": "X"}, {"op": "Number", "value": 0.5}]}
+//
+// "args" holds the children in order and is left out for leaves. payloads get their own keys:
+// Rule "index", Number/Boolean "value", Wave "kind", Turbulence "octaves", Noise "seed", Palette
+// "colours" as [r, g, b] triples. non-finite floats are written as the strings "NaN", "inf" and "-inf"
+use crate::{Node, ParseError, WaveKind};
+use crate::utils::Colour;
+
+fn float(value: f32) -> String {
+    if value.is_finite() {
+        format!("{:?}", value)
+    } else {
+        format!("\"{:?}\"", value)
+    }
+}
+
+fn write_node(node: &Node, out: &mut String) {
+    out.push_str(&format!("{{\"op\":\"{}\"", node.kind_name()));
+    match node {
+        Node::Rule(index) => out.push_str(&format!(",\"index\":{}", index)),
+        Node::Number(value) => out.push_str(&format!(",\"value\":{}", float(*value))),
+        Node::Boolean(value) => out.push_str(&format!(",\"value\":{}", value)),
+        Node::Wave(_, _, _, kind) => out.push_str(&format!(",\"kind\":\"{:?}\"", kind)),
+        Node::Turbulence(_, _, octaves) => out.push_str(&format!(",\"octaves\":{}", octaves)),
+        Node::Noise(_, _, seed) => out.push_str(&format!(",\"seed\":{}", seed)),
+        Node::Palette(_, colours) => {
+            let colours: Vec<String> = colours.iter()
+                .map(|c| format!("[{},{},{}]", float(c.r), float(c.g), float(c.b)))
+                .collect();
+            out.push_str(&format!(",\"colours\":[{}]", colours.join(",")));
+        }
+        _ => {}
+    }
+
+    let children = node.children();
+    if !children.is_empty() {
+        out.push_str(",\"args\":[");
+        for (i, child) in children.into_iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            write_node(child, out);
+        }
+        out.push(']');
+    }
+    out.push('}');
+}
+
+// numbers keep their source text so floats are parsed straight to f32, without rounding twice
+#[derive(Debug)]
+pub(crate) enum Json {
+    Null,
+    Bool(bool),
+    Number(String),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+struct Tokenizer<'a> {
+    text: &'a str,
+    position: usize,
+}
+
+impl<'a> Tokenizer<'a> {
+    fn skip_whitespace(&mut self) {
+        let rest = &self.text[self.position..];
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    fn peek(&mut self) -> Result<char, ParseError> {
+        self.skip_whitespace();
+        self.text[self.position..].chars().next().ok_or(ParseError::UnexpectedEnd)
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), ParseError> {
+        let found = self.peek()?;
+        if found != expected {
+            return Err(ParseError::UnexpectedChar { position: self.position, found });
+        }
+        self.position += expected.len_utf8();
+        Ok(())
+    }
+
+    fn value(&mut self) -> Result<Json, ParseError> {
+        match self.peek()? {
+            '{' => self.object(),
+            '[' => self.array(),
+            '"' => Ok(Json::String(self.string()?)),
+            '-' | '0'..='9' => {
+                let rest = &self.text[self.position..];
+                let end = rest.find(|c: char| !(c.is_ascii_digit() || "+-.eE".contains(c))).unwrap_or(rest.len());
+                self.position += end;
+                Ok(Json::Number(rest[..end].to_string()))
+            }
+            _ => {
+                for (literal, value) in [("true", Json::Bool(true)), ("false", Json::Bool(false)), ("null", Json::Null)] {
+                    if self.text[self.position..].starts_with(literal) {
+                        self.position += literal.len();
+                        return Ok(value);
+                    }
+                }
+                Err(ParseError::UnexpectedChar { position: self.position, found: self.peek()? })
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, ParseError> {
+        self.expect('"')?;
+        let mut out = String::new();
+        let mut chars = self.text[self.position..].char_indices();
+        while let Some((offset, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.position += offset + 1;
+                    return Ok(out);
+                }
+                '\\' => match chars.next() {
+                    Some((_, '"')) => out.push('"'),
+                    Some((_, '\\')) => out.push('\\'),
+                    Some((_, '/')) => out.push('/'),
+                    Some((_, 'n')) => out.push('\n'),
+                    Some((_, 't')) => out.push('\t'),
+                    Some((escape_offset, found)) => {
+                        return Err(ParseError::UnexpectedChar { position: self.position + escape_offset, found });
+                    }
+                    None => break,
+                },
+                c => out.push(c),
+            }
+        }
+        Err(ParseError::UnexpectedEnd)
+    }
+
+    fn array(&mut self) -> Result<Json, ParseError> {
+        self.expect('[')?;
+        let mut items = Vec::new();
+        if self.peek()? == ']' {
+            self.position += 1;
+            return Ok(Json::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            match self.peek()? {
+                ',' => self.position += 1,
+                ']' => {
+                    self.position += 1;
+                    return Ok(Json::Array(items));
+                }
+                found => return Err(ParseError::UnexpectedChar { position: self.position, found }),
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, ParseError> {
+        self.expect('{')?;
+        let mut fields = Vec::new();
+        if self.peek()? == '}' {
+            self.position += 1;
+            return Ok(Json::Object(fields));
+        }
+        loop {
+            let key = self.string()?;
+            self.expect(':')?;
+            fields.push((key, self.value()?));
+            match self.peek()? {
+                ',' => self.position += 1,
+                '}' => {
+                    self.position += 1;
+                    return Ok(Json::Object(fields));
+                }
+                found => return Err(ParseError::UnexpectedChar { position: self.position, found }),
+            }
+        }
+    }
+}
+
+fn field<'a>(fields: &'a [(String, Json)], op: &str, name: &'static str) -> Result<&'a Json, ParseError> {
+    fields.iter()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value)
+        .ok_or_else(|| ParseError::MissingField { op: op.to_string(), field: name })
+}
+
+fn to_float(value: &Json, op: &str, name: &'static str) -> Result<f32, ParseError> {
+    match value {
+        Json::Number(text) | Json::String(text) => text.parse().map_err(|_| ParseError::InvalidValue { op: op.to_string(), field: name }),
+        _ => Err(ParseError::InvalidValue { op: op.to_string(), field: name }),
+    }
+}
+
+pub(crate) fn to_node(json: &Json) -> Result<Node, ParseError> {
+    let Json::Object(fields) = json else {
+        return Err(ParseError::ExpectedObject);
+    };
+    let op = match field(fields, "?", "op")? {
+        Json::String(op) => op.as_str(),
+        _ => return Err(ParseError::InvalidValue { op: "?".to_string(), field: "op" }),
+    };
+    let invalid = |name: &'static str| ParseError::InvalidValue { op: op.to_string(), field: name };
+
+    let args = match fields.iter().find(|(key, _)| key == "args") {
+        Some((_, Json::Array(args))) => args.iter().map(to_node).collect::<Result<Vec<_>, _>>()?,
+        Some(_) => return Err(invalid("args")),
+        None => Vec::new(),
+    };
+    let expected = match op {
+        "X" | "Y" | "Radius" | "Random" | "Rule" | "Number" | "Boolean" => 0,
+        "Sqrt" | "Sin" | "Cos" | "Exp" | "Sigmoid" | "Wrap" | "Sign" | "Tan" | "Abs" | "Log" | "Floor" | "Ceil" | "Round" | "Fract" | "Palette" => 1,
+        "Add" | "Sub" | "Min" | "Max" | "Mult" | "Div" | "Modulo" | "Gt" | "Step" | "Pow" | "Turbulence" | "Noise" => 2,
+        "Triple" | "If" | "Smoothmin" | "Clamp" | "Smoothstep" | "Lerp" | "Wave" => 3,
+        "Quad" | "Mix" | "MixUnbounded" => 4,
+        "Remap" => 5,
+        _ => return Err(ParseError::UnknownOp(op.to_string())),
+    };
+    if args.len() != expected {
+        return Err(ParseError::WrongArity { op: op.to_string(), expected, found: args.len() });
+    }
+
+    let mut args = args.into_iter().map(Box::new);
+    let mut child = || args.next().expect("arity was checked");
+    let node = match op {
+        "X" => Node::X,
+        "Y" => Node::Y,
+        "Radius" => Node::Radius,
+        "Random" => Node::Random,
+        "Rule" => match field(fields, op, "index")? {
+            Json::Number(text) => Node::Rule(text.parse().map_err(|_| invalid("index"))?),
+            _ => return Err(invalid("index")),
+        },
+        "Number" => Node::Number(to_float(field(fields, op, "value")?, op, "value")?),
+        "Boolean" => match field(fields, op, "value")? {
+            Json::Bool(value) => Node::Boolean(*value),
+            _ => return Err(invalid("value")),
+        },
+        "Sqrt" => Node::Sqrt(child()),
+        "Sin" => Node::Sin(child()),
+        "Cos" => Node::Cos(child()),
+        "Exp" => Node::Exp(child()),
+        "Sigmoid" => Node::Sigmoid(child()),
+        "Wrap" => Node::Wrap(child()),
+        "Sign" => Node::Sign(child()),
+        "Tan" => Node::Tan(child()),
+        "Abs" => Node::Abs(child()),
+        "Log" => Node::Log(child()),
+        "Floor" => Node::Floor(child()),
+        "Ceil" => Node::Ceil(child()),
+        "Round" => Node::Round(child()),
+        "Fract" => Node::Fract(child()),
+        "Add" => Node::Add(child(), child()),
+        "Sub" => Node::Sub(child(), child()),
+        "Min" => Node::Min(child(), child()),
+        "Max" => Node::Max(child(), child()),
+        "Mult" => Node::Mult(child(), child()),
+        "Div" => Node::Div(child(), child()),
+        "Modulo" => Node::Modulo(child(), child()),
+        "Gt" => Node::Gt(child(), child()),
+        "Step" => Node::Step(child(), child()),
+        "Pow" => Node::Pow(child(), child()),
+        "Triple" => Node::Triple(child(), child(), child()),
+        "If" => Node::If(child(), child(), child()),
+        "Smoothmin" => Node::Smoothmin(child(), child(), child()),
+        "Clamp" => Node::Clamp(child(), child(), child()),
+        "Smoothstep" => Node::Smoothstep(child(), child(), child()),
+        "Lerp" => Node::Lerp(child(), child(), child()),
+        "Quad" => Node::Quad(child(), child(), child(), child()),
+        "Mix" => Node::Mix(child(), child(), child(), child()),
+        "MixUnbounded" => Node::MixUnbounded(child(), child(), child(), child()),
+        "Remap" => Node::Remap(child(), child(), child(), child(), child()),
+        "Wave" => {
+            let kind = match field(fields, op, "kind")? {
+                Json::String(kind) if kind == "Sin" => WaveKind::Sin,
+                Json::String(kind) if kind == "Cos" => WaveKind::Cos,
+                Json::String(kind) if kind == "Tan" => WaveKind::Tan,
+                _ => return Err(invalid("kind")),
+            };
+            Node::Wave(child(), child(), child(), kind)
+        }
+        "Turbulence" => match field(fields, op, "octaves")? {
+            Json::Number(text) => Node::Turbulence(child(), child(), text.parse().map_err(|_| invalid("octaves"))?),
+            _ => return Err(invalid("octaves")),
+        },
+        "Noise" => match field(fields, op, "seed")? {
+            Json::Number(text) => Node::Noise(child(), child(), text.parse().map_err(|_| invalid("seed"))?),
+            _ => return Err(invalid("seed")),
+        },
+        "Palette" => {
+            let Json::Array(entries) = field(fields, op, "colours")? else {
+                return Err(invalid("colours"));
+            };
+            let colours = entries.iter()
+                .map(|entry| match entry {
+                    Json::Array(channels) if channels.len() == 3 => Ok(Colour {
+                        r: to_float(&channels[0], op, "colours")?,
+                        g: to_float(&channels[1], op, "colours")?,
+                        b: to_float(&channels[2], op, "colours")?,
+                    }),
+                    _ => Err(invalid("colours")),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            Node::Palette(child(), colours)
+        }
+        _ => unreachable!("{} was accepted by the arity table", op),
+    };
+    Ok(node)
+}
+
+impl Node {
+    pub fn to_json(&self) -> String {
+        let mut out = String::new();
+        write_node(self, &mut out);
+        out
+    }
+
+    pub fn from_json(text: &str) -> Result<Node, ParseError> {
+        let mut tokenizer = Tokenizer { text, position: 0 };
+        let json = tokenizer.value()?;
+        tokenizer.skip_whitespace();
+        if tokenizer.position != text.len() {
+            return Err(ParseError::TrailingCharacters(tokenizer.position));
+        }
+        to_node(&json)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Grammar;
+    use crate::utils::fnv1a;
+
+    #[test]
+    fn test_json_round_trip() {
+        for name in ["json", "round", "trip", "spiderman"] {
+            let tree = Grammar::default(fnv1a(name)).gen_rule(0, 14).unwrap();
+            assert_eq!(Node::from_json(&tree.to_json()), Ok(*tree));
+        }
+
+        let exotic = Node::Palette(
+            Box::new(Node::Turbulence(
+                Box::new(Node::Wave(Box::new(Node::X), Box::new(Node::Rule(2)), Box::new(Node::Number(f32::INFINITY)), WaveKind::Tan)),
+                Box::new(Node::Step(Box::new(Node::Boolean(true)), Box::new(Node::Sign(Box::new(Node::Y))))),
+                5,
+            )),
+            vec![Colour { r: 0.25, g: -1.0, b: 0.1 }],
+        );
+        assert_eq!(Node::from_json(&exotic.to_json()), Ok(exotic));
+        assert_eq!(Node::Add(Box::new(Node::X), Box::new(Node::Number(0.5))).to_json(), r#"{"op":"Add","args":[{"op":"X"},{"op":"Number","value":0.5}]}"#);
+        assert_eq!(Node::from_json(" { \"args\" : [ ], \"op\" : \"Y\" } "), Ok(Node::Y));
+    }
+
+    #[test]
+    fn test_json_rejects_malformed_input() {
+        assert_eq!(Node::from_json(r#"{"op":"Add","args":[{"op":"X"}"#), Err(ParseError::UnexpectedEnd));
+        assert_eq!(Node::from_json(r#"{"op":"Add" "args":[]}"#), Err(ParseError::UnexpectedChar { position: 12, found: '"' }));
+        assert_eq!(Node::from_json(r#"{"op":"Blur"}"#), Err(ParseError::UnknownOp("Blur".to_string())));
+        assert_eq!(
+            Node::from_json(r#"{"op":"Sin","args":[{"op":"X"},{"op":"Y"}]}"#),
+            Err(ParseError::WrongArity { op: "Sin".to_string(), expected: 1, found: 2 })
+        );
+        assert_eq!(Node::from_json(r#"{"op":"Number"}"#), Err(ParseError::MissingField { op: "Number".to_string(), field: "value" }));
+        assert_eq!(Node::from_json(r#"{"op":"X"} x"#), Err(ParseError::TrailingCharacters(11)));
+
+        let error = Node::from_json(r#"{"op":"Number","value":"lots"}"#).unwrap_err();
+        assert_eq!(error.to_string(), "invalid \"value\" for Number");
+    }
+}
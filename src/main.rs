@@ -1,4 +1,4 @@
-use randomart::{utils::{ fnv1a, render_pixels, PixelCoordinates }, Grammar};
+use randomart::{utils::{ render_pixels, PixelCoordinates, DEFAULT_HEIGHT, DEFAULT_WIDTH }, Grammar};
 use std::{env, path::PathBuf};
 
 fn get_output_path(file_name: &str) -> PathBuf {
@@ -21,21 +21,20 @@ fn main() {
     });
     let output_filename = format!("{}.png", args[3]);
 
-    let width: u32 = args.get(4).map_or(400, |arg| {
+    let width: u32 = args.get(4).map_or(DEFAULT_WIDTH, |arg| {
         arg.parse().unwrap_or_else(|_| {
             eprintln!("ERR: invalid width, must be a positive integer");
             std::process::exit(1);
         })
     });
-    let height: u32 = args.get(5).map_or(400, |arg| {
+    let height: u32 = args.get(5).map_or(DEFAULT_HEIGHT, |arg| {
         arg.parse().unwrap_or_else(|_| {
             eprintln!("ERR: invalid height, must be a positive integer");
             std::process::exit(1);
         })
     });
 
-    let seed = fnv1a(&string);
-    let mut grammar = Grammar::default(seed);
+    let mut grammar = Grammar::default_from_str(&string);
     
     let start_rule = 0;
     let mut generated_node = grammar.gen_rule(start_rule, depth).unwrap();
@@ -47,7 +46,7 @@ fn main() {
         generated_node.eval_rgb(coords.x, coords.y)
     };
     
-    let img = render_pixels(rgb_function, width, height);
+    let img = render_pixels(rgb_function, width, height, None);
 
     let output_filepath = get_output_path(&output_filename);
     img.save(output_filepath.clone()).expect("failed to save the image");
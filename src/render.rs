@@ -0,0 +1,757 @@
+use crate::Node;
+use crate::utils::{colour_to_rgb8, seeded_hash2d, Colour, PixelCoordinates, DEFAULT_GAMMA, DEFAULT_HEIGHT, DEFAULT_WIDTH};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Write};
+#[cfg(feature = "image")]
+use std::path::Path;
+
+// the colour of the bars around a letterboxed render, black
+pub const LETTERBOX_FILL: Colour = Colour { r: -1.0, g: -1.0, b: -1.0 };
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Canvas {
+    pub width: u32,
+    pub height: u32,
+    // letterbox instead of stretching: [-1, 1] fills the largest centred square, so circles stay
+    // circular on non-square canvases, and the bars either side are `LETTERBOX_FILL`
+    pub preserve_aspect: bool,
+    // supersampling: each pixel averages `aa` x `aa` jittered samples around its centre. 0 and 1
+    // both take the single sample at the centre
+    pub aa: u32,
+}
+
+impl Canvas {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self { width, height, preserve_aspect: false, aa: 1 }
+    }
+
+    pub fn with_preserved_aspect(self) -> Self {
+        Self { preserve_aspect: true, ..self }
+    }
+
+    pub fn with_antialiasing(self, aa: u32) -> Self {
+        Self { aa, ..self }
+    }
+
+    // maps a pixel into [-1, 1] the same way `render_pixels` does: (0, 0) is the top-left corner
+    // at (-1, -1) and y grows downwards. with `preserve_aspect` the corners are those of the
+    // centred square, and pixels in the bars land outside [-1, 1]
+    pub fn coordinates(&self, px: u32, py: u32) -> PixelCoordinates {
+        self.point(px as f32, py as f32)
+    }
+
+    // false for the pixels in a letterboxed canvas's bars, which render as `LETTERBOX_FILL`
+    pub fn in_picture(&self, px: u32, py: u32) -> bool {
+        let (left, top, width, height) = self.picture();
+        (left..left + width).contains(&px) && (top..top + height).contains(&py)
+    }
+
+    // the (left, top, width, height) of the pixels [-1, 1] is mapped onto
+    fn picture(&self) -> (u32, u32, u32, u32) {
+        if !self.preserve_aspect {
+            return (0, 0, self.width, self.height);
+        }
+        let side = self.width.min(self.height);
+        ((self.width - side) / 2, (self.height - side) / 2, side, side)
+    }
+
+    // `coordinates` at fractional pixel positions. an axis one pixel across has nothing to span
+    // [-1, 1] with, so it sits at the centre, 0.0
+    fn point(&self, px: f32, py: f32) -> PixelCoordinates {
+        let (left, top, width, height) = self.picture();
+        let axis = |p: f32, start: u32, length: u32| {
+            if length > 1 { ((p - start as f32) / (length - 1) as f32) * 2.0 - 1.0 } else { 0.0 }
+        };
+        PixelCoordinates { x: axis(px, left, width), y: axis(py, top, height) }
+    }
+}
+
+// one pixel of `render_colours`: `LETTERBOX_FILL` in the bars, otherwise `eval_rgb` at the
+// pixel, supersampled when the canvas asks for it
+fn render_pixel(root: &Node, canvas: &Canvas, px: u32, py: u32) -> Colour {
+    if !canvas.in_picture(px, py) {
+        return LETTERBOX_FILL;
+    }
+    if canvas.aa > 1 {
+        return supersample(root, canvas, px, py);
+    }
+    let PixelCoordinates { x, y } = canvas.coordinates(px, py);
+    root.eval_rgb(x, y)
+}
+
+// the mean `eval_rgb` over the pixel's `aa` x `aa` sub-cells, each sampled at a spot picked by
+// hashing its index, so the jitter is the same on every run
+fn supersample(root: &Node, canvas: &Canvas, px: u32, py: u32) -> Colour {
+    let aa = canvas.aa;
+    let mut sum = Colour { r: 0.0, g: 0.0, b: 0.0 };
+    for sy in 0..aa {
+        for sx in 0..aa {
+            let (ix, iy) = ((px * aa + sx) as i32, (py * aa + sy) as i32);
+            let jitter = |seed: u32| (seeded_hash2d(ix, iy, seed) + 1.0) / 2.0;
+            let offset = |sub: u32, seed: u32| (sub as f32 + jitter(seed)) / aa as f32 - 0.5;
+            let PixelCoordinates { x, y } = canvas.point(px as f32 + offset(sx, 1), py as f32 + offset(sy, 2));
+            sum = sum + root.eval_rgb(x, y);
+        }
+    }
+    sum * (1.0 / (aa * aa) as f32)
+}
+
+impl Default for Canvas {
+    // `DEFAULT_WIDTH` x `DEFAULT_HEIGHT`, stretched
+    fn default() -> Self {
+        Self::new(DEFAULT_WIDTH, DEFAULT_HEIGHT)
+    }
+}
+
+// the rectangle of the plane a render covers, for zooming and panning away from [-1, 1].
+// the left column lands on `x_min` and the top row on `y_min`, so swapping a pair mirrors the image
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Viewport {
+    pub x_min: f32,
+    pub x_max: f32,
+    pub y_min: f32,
+    pub y_max: f32,
+}
+
+impl Viewport {
+    // the point pixel (px, py) of a `width` x `height` render samples
+    pub fn coordinates(&self, px: u32, py: u32, width: u32, height: u32) -> PixelCoordinates {
+        let x = self.x_min + (px as f32 / (width - 1) as f32) * (self.x_max - self.x_min);
+        let y = self.y_min + (py as f32 / (height - 1) as f32) * (self.y_max - self.y_min);
+        PixelCoordinates { x, y }
+    }
+}
+
+impl Default for Viewport {
+    // the [-1, 1] square every other renderer covers
+    fn default() -> Self {
+        Self { x_min: -1.0, x_max: 1.0, y_min: -1.0, y_max: 1.0 }
+    }
+}
+
+// evaluates `root` like `eval_rgb` at every pixel, row-major
+pub fn render_colours(root: &Node, canvas: &Canvas) -> Vec<Colour> {
+    let pixel_count = (canvas.width * canvas.height) as usize;
+    let (Node::Triple(r, g, b), 0..=1) = (root, canvas.aa) else {
+        // supersampled canvases, and a Palette (or anything else `eval_rgb` understands), are
+        // evaluated per pixel as is
+        return (0..pixel_count as u32)
+            .map(|i| render_pixel(root, canvas, i % canvas.width, i / canvas.width))
+            .collect();
+    };
+
+    // a channel without X/Y is the same everywhere, so it is evaluated once and broadcast
+    let constant = |channel: &Node| channel.is_constant().then(|| channel.eval(0.0, 0.0));
+    let (r_constant, g_constant, b_constant) = (constant(r), constant(g), constant(b));
+
+    let mut colours = Vec::with_capacity(pixel_count);
+    for py in 0..canvas.height {
+        for px in 0..canvas.width {
+            if !canvas.in_picture(px, py) {
+                colours.push(LETTERBOX_FILL);
+                continue;
+            }
+            let PixelCoordinates { x, y } = canvas.coordinates(px, py);
+            colours.push(Colour {
+                r: r_constant.unwrap_or_else(|| r.eval(x, y)),
+                g: g_constant.unwrap_or_else(|| g.eval(x, y)),
+                b: b_constant.unwrap_or_else(|| b.eval(x, y)),
+            });
+        }
+    }
+    colours
+}
+
+// `render_colours` over `viewport` instead of [-1, 1], e.g. to re-render a crop at a higher resolution
+pub fn render_viewport(root: &Node, viewport: &Viewport, width: u32, height: u32) -> Vec<Colour> {
+    let mut colours = Vec::with_capacity((width * height) as usize);
+    for py in 0..height {
+        for px in 0..width {
+            let PixelCoordinates { x, y } = viewport.coordinates(px, py, width, height);
+            colours.push(root.eval_rgb(x, y));
+        }
+    }
+    colours
+}
+
+// health of a render: pixels with any non-finite channel, NaN channels among those, and the
+// (min, max) of each of r, g, b over the finite values only
+#[derive(Clone, Debug, PartialEq)]
+pub struct RenderStats {
+    pub failed_pixels: usize,
+    pub nan_channels: usize,
+    pub min_max_per_channel: [(f32, f32); 3],
+}
+
+impl Default for RenderStats {
+    fn default() -> Self {
+        Self { failed_pixels: 0, nan_channels: 0, min_max_per_channel: [(f32::INFINITY, f32::NEG_INFINITY); 3] }
+    }
+}
+
+impl RenderStats {
+    fn record(&mut self, colour: &Colour) {
+        let channels = [colour.r, colour.g, colour.b];
+        if channels.iter().any(|channel| !channel.is_finite()) {
+            self.failed_pixels += 1;
+        }
+        for (channel, (min, max)) in channels.into_iter().zip(&mut self.min_max_per_channel) {
+            if channel.is_nan() {
+                self.nan_channels += 1;
+            } else if channel.is_finite() {
+                *min = min.min(channel);
+                *max = max.max(channel);
+            }
+        }
+    }
+}
+
+// `render_colours` plus a `RenderStats` over the buffer, to judge whether a seed is degenerate
+pub fn render_colours_with_stats(root: &Node, canvas: &Canvas) -> (Vec<Colour>, RenderStats) {
+    let colours = render_colours(root, canvas);
+    let mut stats = RenderStats::default();
+    for colour in &colours {
+        stats.record(colour);
+    }
+    (colours, stats)
+}
+
+// treats the scalar `field` as a height map and encodes each pixel's surface normal
+// (-dz/dx, -dz/dy, 1), normalised, as r, g, b. channels stay in [-1, 1] like every other buffer, so
+// a flat field is (0, 0, 1): the usual (0.5, 0.5, 1.0) normal-map blue once mapped into [0, 1]. the
+// gradient is the central difference between neighbouring pixels (one-sided along the edges), per
+// unit of x and y so the tilt does not depend on the resolution
+pub fn render_normal_map(field: &Node, width: u32, height: u32) -> Vec<Colour> {
+    let canvas = Canvas::new(width, height);
+    let heights: Vec<f32> = (0..width * height)
+        .map(|i| {
+            let PixelCoordinates { x, y } = canvas.coordinates(i % width, i / width);
+            field.eval(x, y)
+        })
+        .collect();
+    let height_at = |px: u32, py: u32| heights[(py * width + px) as usize];
+    let slope = |rise: f32, run: f32| if run != 0.0 { rise / run } else { 0.0 };
+
+    let mut colours = Vec::with_capacity(heights.len());
+    for py in 0..height {
+        for px in 0..width {
+            let (left, right) = (px.saturating_sub(1), (px + 1).min(width - 1));
+            let (up, down) = (py.saturating_sub(1), (py + 1).min(height - 1));
+            let dz_dx = slope(
+                height_at(right, py) - height_at(left, py),
+                canvas.coordinates(right, py).x - canvas.coordinates(left, py).x,
+            );
+            let dz_dy = slope(
+                height_at(px, down) - height_at(px, up),
+                canvas.coordinates(px, down).y - canvas.coordinates(px, up).y,
+            );
+            let length = (dz_dx * dz_dx + dz_dy * dz_dy + 1.0).sqrt();
+            colours.push(Colour { r: -dz_dx / length, g: -dz_dy / length, b: 1.0 / length });
+        }
+    }
+    colours
+}
+
+const ASCII_RAMP: &[u8] = b" .:-=+*#%@";
+
+// terminal preview: one character per pixel, picked from a dark-to-bright ramp by the pixel's
+// luma (BT.601 weights over channels mapped from [-1, 1] into [0, 1]). rows end with '\n'
+pub fn render_ascii(root: &Node, width: u32, height: u32) -> String {
+    render_canvas_ascii(root, &Canvas::new(width, height))
+}
+
+// `render_ascii` on any canvas
+pub fn render_canvas_ascii(root: &Node, canvas: &Canvas) -> String {
+    let colours = render_colours(root, canvas);
+    let mut out = String::with_capacity(colours.len() + canvas.height as usize);
+    for row in colours.chunks(canvas.width as usize) {
+        for colour in row {
+            let unit = |channel: f32| ((channel + 1.0) / 2.0).clamp(0.0, 1.0);
+            let luma = 0.299 * unit(colour.r) + 0.587 * unit(colour.g) + 0.114 * unit(colour.b);
+            let index = (luma * (ASCII_RAMP.len() - 1) as f32).round() as usize;
+            out.push(ASCII_RAMP[index.min(ASCII_RAMP.len() - 1)] as char);
+        }
+        out.push('\n');
+    }
+    out
+}
+
+// `eval_rgb` over a `width` x `height` canvas as raw RGB8 bytes, `width * height * 3` of them.
+// rows run top to bottom: pixel (0, 0) is the top-left corner at (-1, -1), and y grows downwards
+// (see `Canvas::coordinates`). channels map [-1, 1] onto 0..=255, clamping outside it, and are
+// encoded with `gamma` (`DEFAULT_GAMMA` when None, Some(1.0) for the plain linear mapping)
+pub fn render_to_buffer(root: &Node, width: u32, height: u32, gamma: Option<f32>) -> Vec<u8> {
+    render_canvas_to_buffer(root, &Canvas::new(width, height), gamma)
+}
+
+// `render_to_buffer` on any canvas, e.g. one letterboxed to keep the aspect ratio instead of stretching
+pub fn render_canvas_to_buffer(root: &Node, canvas: &Canvas, gamma: Option<f32>) -> Vec<u8> {
+    let gamma = gamma.unwrap_or(DEFAULT_GAMMA);
+    render_colours(root, canvas)
+        .iter()
+        .flat_map(|colour| colour_to_rgb8(colour, gamma))
+        .collect()
+}
+
+// `render_to_buffer` with the rows spread over rayon's thread pool. each task owns a disjoint
+// row of the buffer, so the output is byte for byte the same as the serial render
+#[cfg(feature = "rayon")]
+pub fn render_parallel(root: &Node, width: u32, height: u32, gamma: Option<f32>) -> Vec<u8> {
+    render_canvas_parallel(root, &Canvas::new(width, height), gamma)
+}
+
+// `render_parallel` on any canvas, the same bytes as `render_canvas_to_buffer`
+#[cfg(feature = "rayon")]
+pub fn render_canvas_parallel(root: &Node, canvas: &Canvas, gamma: Option<f32>) -> Vec<u8> {
+    use rayon::prelude::*;
+
+    let gamma = gamma.unwrap_or(DEFAULT_GAMMA);
+    let mut buffer = vec![0u8; (canvas.width * canvas.height * 3) as usize];
+    buffer.par_chunks_mut((canvas.width * 3).max(1) as usize).enumerate().for_each(|(py, row)| {
+        for (px, pixel) in row.chunks_mut(3).enumerate() {
+            pixel.copy_from_slice(&colour_to_rgb8(&render_pixel(root, canvas, px as u32, py as u32), gamma));
+        }
+    });
+    buffer
+}
+
+// `render_to_buffer` as a binary (P6) PPM, which needs no image crate
+pub fn write_ppm<W: Write>(root: &Node, width: u32, height: u32, gamma: Option<f32>, w: &mut W) -> io::Result<()> {
+    write_canvas_ppm(root, &Canvas::new(width, height), gamma, w)
+}
+
+// `write_ppm` on any canvas
+pub fn write_canvas_ppm<W: Write>(root: &Node, canvas: &Canvas, gamma: Option<f32>, w: &mut W) -> io::Result<()> {
+    write!(w, "P6\n{} {}\n255\n", canvas.width, canvas.height)?;
+    w.write_all(&render_canvas_to_buffer(root, canvas, gamma))
+}
+
+// `render_to_buffer` saved as an 8-bit RGB PNG at `path`
+#[cfg(feature = "image")]
+pub fn render_png(root: &Node, width: u32, height: u32, gamma: Option<f32>, path: &Path) -> io::Result<()> {
+    render_canvas_png(root, &Canvas::new(width, height), gamma, path)
+}
+
+// `render_png` on any canvas
+#[cfg(feature = "image")]
+pub fn render_canvas_png(root: &Node, canvas: &Canvas, gamma: Option<f32>, path: &Path) -> io::Result<()> {
+    let bytes = render_canvas_to_buffer(root, canvas, gamma);
+    let image = image::RgbImage::from_raw(canvas.width, canvas.height, bytes).expect("one rgb triple per pixel");
+    image.save_with_format(path, image::ImageFormat::Png).map_err(io::Error::other)
+}
+
+// terminal preview in 24-bit colour: each character is an upper half block whose foreground is
+// one pixel and background the pixel below it, so a row of text covers two rows of pixels. an
+// odd last row leaves the background alone. rows end with a reset and '\n'
+pub fn render_ansi(root: &Node, width: u32, height: u32) -> String {
+    render_canvas_ansi(root, &Canvas::new(width, height))
+}
+
+// `render_ansi` on any canvas
+pub fn render_canvas_ansi(root: &Node, canvas: &Canvas) -> String {
+    let (width, height) = (canvas.width, canvas.height);
+    let buffer = render_canvas_to_buffer(root, canvas, None);
+    let pixel = |px: u32, py: u32| {
+        let start = ((py * width + px) * 3) as usize;
+        (buffer[start], buffer[start + 1], buffer[start + 2])
+    };
+
+    let mut out = String::new();
+    for py in (0..height).step_by(2) {
+        for px in 0..width {
+            let (r, g, b) = pixel(px, py);
+            out.push_str(&format!("\x1b[38;2;{};{};{}m", r, g, b));
+            if py + 1 < height {
+                let (r, g, b) = pixel(px, py + 1);
+                out.push_str(&format!("\x1b[48;2;{};{};{}m", r, g, b));
+            }
+            out.push('▀');
+        }
+        out.push_str("\x1b[0m\n");
+    }
+    out
+}
+
+// a hash of the tree's shape and payloads, numbers by their bits, without formatting it
+fn tree_hash(root: &Node) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    root.visit(&mut |node| {
+        std::mem::discriminant(node).hash(&mut hasher);
+        match node {
+            Node::Number(value) => value.to_bits().hash(&mut hasher),
+            Node::Boolean(value) => value.hash(&mut hasher),
+            Node::Rule(index) => index.hash(&mut hasher),
+            Node::Wave(_, _, _, kind) => std::mem::discriminant(kind).hash(&mut hasher),
+            Node::Turbulence(_, _, octaves) => octaves.hash(&mut hasher),
+            Node::Noise(_, _, seed) => seed.hash(&mut hasher),
+            Node::Palette(_, colours) => {
+                for colour in colours {
+                    [colour.r, colour.g, colour.b].map(f32::to_bits).hash(&mut hasher);
+                }
+            }
+            _ => {}
+        }
+    });
+    hasher.finish()
+}
+
+// remembers rendered buffers keyed on (`tree_hash`, canvas), so re-rendering an unchanged tree on
+// the same canvas is free. each entry keeps its tree, and a different tree with the same hash
+// replaces the entry instead of being handed its buffer
+#[derive(Default)]
+pub struct RenderCache {
+    entries: HashMap<(u64, Canvas), (Node, Vec<Colour>)>,
+}
+
+impl RenderCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get_or_render(&mut self, root: &Node, canvas: &Canvas) -> &[Colour] {
+        self.get_or_render_with(root, canvas, render_colours)
+    }
+
+    // like `get_or_render`, but `render` produces the buffer on a cache miss
+    pub fn get_or_render_with<F>(&mut self, root: &Node, canvas: &Canvas, render: F) -> &[Colour]
+    where
+        F: FnOnce(&Node, &Canvas) -> Vec<Colour>
+    {
+        let key = (tree_hash(root), *canvas);
+        if self.entries.get(&key).is_some_and(|(tree, _)| !tree.tree_diff(root).is_empty()) {
+            self.entries.remove(&key);
+        }
+        &self.entries.entry(key).or_insert_with(|| (root.clone(), render(root, canvas))).1
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    fn gradient() -> Node {
+        Node::Triple(Box::new(Node::X), Box::new(Node::Y), Box::new(Node::Number(0.5)))
+    }
+
+    #[test]
+    fn test_constant_channels_match_per_pixel_eval() {
+        let root = Node::Triple(
+            Box::new(Node::Sin(Box::new(Node::Number(0.7)))),
+            Box::new(Node::Mult(Box::new(Node::X), Box::new(Node::Y))),
+            Box::new(Node::Exp(Box::new(Node::Number(-0.3)))),
+        );
+        let canvas = Canvas::new(5, 3);
+        let colours = render_colours(&root, &canvas);
+
+        assert_eq!(colours.len(), 15);
+        for py in 0..canvas.height {
+            for px in 0..canvas.width {
+                let coords = canvas.coordinates(px, py);
+                assert_eq!(colours[(py * canvas.width + px) as usize], root.eval_rgb(coords.x, coords.y));
+            }
+        }
+        assert_eq!(render_colours(&Node::X, &canvas), vec![Colour { r: 0.0, g: 0.0, b: 0.0 }; 15]);
+    }
+
+    #[test]
+    fn test_preserve_aspect_letterboxes() {
+        // the picture is the middle 100 columns, with 50-pixel bars either side
+        let wide = Canvas::new(200, 100).with_preserved_aspect();
+        let corner = wide.coordinates(149, 99);
+        assert_eq!((corner.x, corner.y), (1.0, 1.0));
+        let origin = wide.coordinates(50, 0);
+        assert_eq!((origin.x, origin.y), (-1.0, -1.0));
+        assert!(wide.in_picture(50, 0) && wide.in_picture(149, 99));
+        assert!(!wide.in_picture(49, 50) && !wide.in_picture(150, 50));
+
+        let tall = Canvas::new(50, 150).with_preserved_aspect();
+        let corner = tall.coordinates(49, 99);
+        assert_eq!((corner.x, corner.y), (1.0, 1.0));
+        assert!(!tall.in_picture(0, 49) && tall.in_picture(0, 50));
+
+        let stretched = Canvas::new(200, 100);
+        let corner = stretched.coordinates(199, 99);
+        assert_eq!((corner.x, corner.y), (1.0, 1.0));
+        assert!(stretched.in_picture(0, 0) && stretched.in_picture(199, 99));
+
+        // an axis one pixel across maps to its centre instead of dividing by zero
+        let strip = Canvas::new(5, 1).with_preserved_aspect();
+        assert!(strip.in_picture(2, 0) && !strip.in_picture(1, 0));
+        let centre = strip.coordinates(2, 0);
+        assert_eq!((centre.x, centre.y), (0.0, 0.0));
+        let column = Canvas::new(1, 3).coordinates(0, 2);
+        assert_eq!((column.x, column.y), (0.0, 1.0));
+        assert!(render_colours(&gradient(), &strip.with_antialiasing(3)).iter().all(|c| c.r.is_finite() && c.g.is_finite()));
+    }
+
+    #[test]
+    fn test_render_stats_flags_failed_pixels() {
+        let canvas = Canvas::new(21, 5);
+        let (_, clean) = render_colours_with_stats(&gradient(), &canvas);
+        assert_eq!(clean.failed_pixels, 0);
+        assert_eq!(clean.nan_channels, 0);
+        assert_eq!(clean.min_max_per_channel, [(-1.0, 1.0), (-1.0, 1.0), (0.5, 0.5)]);
+
+        // 1 / (x / 100) right of zero is large enough that exp overflows to infinity
+        let tiny_x = Node::Mult(Box::new(Node::X), Box::new(Node::Number(0.01)));
+        let blow_up = Node::Exp(Box::new(Node::Div(Box::new(Node::Number(1.0)), Box::new(tiny_x))));
+        let root = Node::Triple(Box::new(blow_up), Box::new(Node::Y), Box::new(Node::Number(0.0)));
+        let (colours, stats) = render_colours_with_stats(&root, &canvas);
+        assert!(stats.failed_pixels > 0);
+        assert_eq!(stats.failed_pixels, colours.iter().filter(|c| !c.r.is_finite()).count());
+        assert!(stats.min_max_per_channel[0].1.is_finite());
+    }
+
+    #[test]
+    fn test_normal_map_follows_the_slope() {
+        let flat = render_normal_map(&Node::Number(0.3), 6, 4);
+        assert_eq!(flat.len(), 24);
+        assert!(flat.iter().all(|normal| normal.approx_eq(&Colour { r: 0.0, g: 0.0, b: 1.0 }, 1e-6)));
+
+        // z = x rises by one per unit of x, so the normal leans 45 degrees towards -x
+        let half = std::f32::consts::FRAC_1_SQRT_2;
+        let ramp_x = render_normal_map(&Node::X, 6, 4);
+        assert!(ramp_x.iter().all(|normal| normal.approx_eq(&Colour { r: -half, g: 0.0, b: half }, 1e-5)));
+
+        // z = y / 2 leans towards -y, and less steeply
+        let ramp_y = Node::Mult(Box::new(Node::Y), Box::new(Node::Number(0.5)));
+        let tilted = render_normal_map(&ramp_y, 6, 4)[9];
+        let length = 1.25f32.sqrt();
+        assert!(tilted.approx_eq(&Colour { r: 0.0, g: -0.5 / length, b: 1.0 / length }, 1e-5));
+    }
+
+    #[test]
+    fn test_render_ascii_uses_ramp_ends() {
+        let flat = |value: f32| Node::Triple(Box::new(Node::Number(value)), Box::new(Node::Number(value)), Box::new(Node::Number(value)));
+
+        assert_eq!(render_ascii(&flat(1.0), 4, 2), "@@@@\n@@@@\n");
+        assert_eq!(render_ascii(&flat(-1.0), 4, 2), "    \n    \n");
+        let preview = render_ascii(&gradient(), 6, 3);
+        assert_eq!(preview.lines().count(), 3);
+        assert!(preview.lines().all(|line| line.len() == 6));
+    }
+
+    #[test]
+    fn test_render_cache_reuses_buffers() {
+        let calls = Cell::new(0);
+        let counting_render = |root: &Node, canvas: &Canvas| {
+            calls.set(calls.get() + 1);
+            render_colours(root, canvas)
+        };
+
+        let mut cache = RenderCache::new();
+        let canvas = Canvas::new(8, 4);
+        let first = cache.get_or_render_with(&gradient(), &canvas, counting_render).len();
+        let second = cache.get_or_render_with(&gradient(), &canvas, counting_render);
+        assert_eq!(calls.get(), 1);
+        assert_eq!(first, second.len());
+        assert!(second[0].approx_eq(&Colour { r: -1.0, g: -1.0, b: 0.5 }, 1e-6));
+
+        cache.get_or_render_with(&gradient(), &Canvas::new(4, 4), counting_render);
+        cache.get_or_render_with(&Node::Triple(Box::new(Node::Y), Box::new(Node::X), Box::new(Node::X)), &canvas, counting_render);
+        assert_eq!(calls.get(), 3);
+        assert_eq!(cache.len(), 3);
+
+        // a tree filed under another tree's key is not handed that tree's buffer
+        let other = Node::Triple(Box::new(Node::Number(0.5)), Box::new(Node::X), Box::new(Node::Y));
+        let key = (tree_hash(&gradient()), canvas);
+        cache.entries.insert(key, (other.clone(), render_colours(&other, &canvas)));
+        let rendered = cache.get_or_render_with(&gradient(), &canvas, counting_render).to_vec();
+        assert_eq!(calls.get(), 4);
+        assert_eq!(rendered, render_colours(&gradient(), &canvas));
+        assert_ne!(tree_hash(&Node::Number(0.0)), tree_hash(&Node::Number(-0.0)));
+    }
+
+    #[test]
+    #[cfg(feature = "image")]
+    fn test_render_png_writes_a_clamped_image() {
+        let path = std::env::temp_dir().join(format!("randomart-render-png-{}.png", std::process::id()));
+        let root = Node::Triple(Box::new(Node::X), Box::new(Node::Number(3.0)), Box::new(Node::Number(-7.5)));
+        render_png(&root, 4, 4, None, &path).unwrap();
+
+        assert!(std::fs::metadata(&path).unwrap().len() > 0);
+        let image = image::open(&path).unwrap().into_rgb8();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(image.dimensions(), (4, 4));
+        assert_eq!(image.get_pixel(0, 2).0, [0, 255, 0]);
+        assert_eq!(image.get_pixel(3, 2).0, [255, 255, 0]);
+
+        let canvas = Canvas::new(6, 4).with_preserved_aspect();
+        render_canvas_png(&root, &canvas, None, &path).unwrap();
+        let image = image::open(&path).unwrap().into_rgb8();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(image.into_raw(), render_canvas_to_buffer(&root, &canvas, None));
+    }
+
+    #[test]
+    fn test_write_ppm_header_and_length() {
+        let mut out = Vec::new();
+        write_ppm(&gradient(), 5, 3, Some(1.0), &mut out).unwrap();
+
+        let header = b"P6\n5 3\n255\n";
+        assert!(out.starts_with(header));
+        assert_eq!(out.len(), header.len() + 5 * 3 * 3);
+        // top-left pixel: x = y = -1, blue 0.5 -> 191
+        assert_eq!(out[header.len()..header.len() + 3], [0, 0, 191]);
+    }
+
+    #[test]
+    fn test_render_to_buffer_is_row_major_rgb() {
+        let constant = Node::Triple(Box::new(Node::Number(1.0)), Box::new(Node::Number(0.0)), Box::new(Node::Number(-2.0)));
+        let buffer = render_to_buffer(&constant, 6, 4, Some(1.0));
+        assert_eq!(buffer.len(), 6 * 4 * 3);
+        assert!(buffer.chunks(3).all(|pixel| pixel == [255, 128, 0]));
+
+        // green follows y, so it grows from the top row to the bottom one
+        let buffer = render_to_buffer(&gradient(), 2, 3, Some(1.0));
+        assert_eq!(buffer.chunks(3).map(|pixel| pixel[1]).collect::<Vec<_>>(), vec![0, 0, 128, 128, 255, 255]);
+    }
+
+    #[test]
+    #[cfg(feature = "rayon")]
+    fn test_render_parallel_matches_serial() {
+        for name in ["rayon", "spiderman"] {
+            let root = crate::Grammar::default(crate::utils::fnv1a(name)).gen_rule(0, 20).unwrap();
+            assert_eq!(render_parallel(&root, 37, 23, None), render_to_buffer(&root, 37, 23, None));
+            let canvas = Canvas::new(40, 20).with_preserved_aspect().with_antialiasing(2);
+            assert_eq!(render_canvas_parallel(&root, &canvas, None), render_canvas_to_buffer(&root, &canvas, None));
+        }
+    }
+
+    #[test]
+    fn test_wide_canvas_stretches_or_preserves_aspect() {
+        // red is x / 2, so it tells the two mappings apart along the longer axis
+        let root = Node::Triple(
+            Box::new(Node::Mult(Box::new(Node::X), Box::new(Node::Number(0.5)))),
+            Box::new(Node::Y),
+            Box::new(Node::Number(0.0)),
+        );
+        let pixel = |buffer: &[u8], px: usize, py: usize| buffer[(py * 200 + px) * 3..][..3].to_vec();
+
+        let stretched = render_canvas_to_buffer(&root, &Canvas::new(200, 100), Some(1.0));
+        assert_eq!(stretched, render_to_buffer(&root, 200, 100, Some(1.0)));
+        assert_eq!(stretched.len(), 200 * 100 * 3);
+        assert_eq!(pixel(&stretched, 0, 0), [64, 0, 128]);
+        assert_eq!(pixel(&stretched, 199, 99), [191, 255, 128]);
+
+        // letterboxed, the square's corners get the colours of the stretched render's corners and
+        // the bars are black
+        let preserved = render_canvas_to_buffer(&root, &Canvas::new(200, 100).with_preserved_aspect(), Some(1.0));
+        assert_eq!(pixel(&preserved, 50, 0), [64, 0, 128]);
+        assert_eq!(pixel(&preserved, 149, 99), [191, 255, 128]);
+        assert_eq!(pixel(&preserved, 0, 0), [0, 0, 0]);
+        assert_eq!(pixel(&preserved, 199, 50), [0, 0, 0]);
+        let antialiased = Canvas::new(200, 100).with_preserved_aspect().with_antialiasing(2);
+        assert_eq!(render_colours(&root, &antialiased)[0], LETTERBOX_FILL);
+
+        assert_eq!(Canvas::default(), Canvas::new(DEFAULT_WIDTH, DEFAULT_HEIGHT));
+    }
+
+    #[test]
+    fn test_canvas_entry_points_letterbox() {
+        let canvas = Canvas::new(200, 100).with_preserved_aspect();
+        let buffer = render_canvas_to_buffer(&gradient(), &canvas, None);
+
+        let mut ppm = Vec::new();
+        write_canvas_ppm(&gradient(), &canvas, None, &mut ppm).unwrap();
+        assert_eq!(&ppm[..15], b"P6\n200 100\n255\n");
+        assert_eq!(&ppm[15..], buffer.as_slice());
+
+        // the bars are the darkest ramp character and black half blocks
+        let ascii = render_canvas_ascii(&gradient(), &canvas);
+        assert!(ascii.lines().all(|line| line.len() == 200 && line.starts_with(&" ".repeat(50)) && line.ends_with(&" ".repeat(50))));
+        let ansi = render_canvas_ansi(&gradient(), &canvas);
+        assert_eq!(ansi.lines().count(), 50);
+        assert!(ansi.starts_with("\x1b[38;2;0;0;0m\x1b[48;2;0;0;0m▀"));
+        assert_ne!(ansi, render_ansi(&gradient(), 200, 100));
+    }
+
+    #[test]
+    fn test_viewport_zooms_and_mirrors() {
+        let root = Node::Triple(
+            Box::new(Node::Mult(Box::new(Node::X), Box::new(Node::Y))),
+            Box::new(Node::Sin(Box::new(Node::X))),
+            Box::new(Node::Y),
+        );
+        assert_eq!(render_viewport(&root, &Viewport::default(), 9, 9), render_colours(&root, &Canvas::new(9, 9)));
+
+        // [-0.5, 0.5] on 5 pixels hits the same points as the middle 5 of 9 pixels over [-1, 1]
+        let full = render_colours(&root, &Canvas::new(9, 9));
+        let centre = Viewport { x_min: -0.5, x_max: 0.5, y_min: -0.5, y_max: 0.5 };
+        let zoomed = render_viewport(&root, &centre, 5, 5);
+        for py in 0..5 {
+            for px in 0..5 {
+                assert_eq!(zoomed[py * 5 + px], full[(py + 2) * 9 + px + 2]);
+            }
+        }
+
+        let flipped = Viewport { x_min: 0.5, x_max: -0.5, ..centre };
+        let mirrored = render_viewport(&root, &flipped, 5, 5);
+        for py in 0..5 {
+            for px in 0..5 {
+                assert_eq!(mirrored[py * 5 + px], zoomed[py * 5 + 4 - px]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_antialiasing_softens_edges() {
+        // a hard vertical edge part way across one pixel column
+        let edge = Box::new(Node::Gt(Box::new(Node::X), Box::new(Node::Number(0.05))));
+        let root = Node::Triple(edge.clone(), edge.clone(), edge);
+        // variance of the steps between neighbouring pixels along a row: one full jump without
+        // supersampling, spread over smaller steps with it
+        let step_variance = |canvas: Canvas| {
+            let colours = render_colours(&root, &canvas);
+            let steps: Vec<f32> = colours[..canvas.width as usize].windows(2).map(|pair| pair[1].r - pair[0].r).collect();
+            let mean = steps.iter().sum::<f32>() / steps.len() as f32;
+            steps.iter().map(|step| (step - mean).powi(2)).sum::<f32>() / steps.len() as f32
+        };
+
+        let aliased = step_variance(Canvas::new(16, 4));
+        let smoothed = step_variance(Canvas::new(16, 4).with_antialiasing(4));
+        assert!(smoothed < aliased, "{} vs {}", smoothed, aliased);
+        assert_eq!(render_colours(&root, &Canvas::new(16, 4).with_antialiasing(4)), render_colours(&root, &Canvas::new(16, 4).with_antialiasing(4)));
+        assert_eq!(render_colours(&root, &Canvas::new(16, 4).with_antialiasing(1)), render_colours(&root, &Canvas::new(16, 4)));
+    }
+
+    #[test]
+    fn test_gamma_brightens_mid_tones() {
+        let grey = Node::Triple(Box::new(Node::Number(0.0)), Box::new(Node::Number(-0.5)), Box::new(Node::Number(-3.0)));
+        let linear = render_to_buffer(&grey, 1, 1, Some(1.0));
+        assert_eq!(linear, [128, 64, 0]);
+
+        let encoded = render_to_buffer(&grey, 1, 1, None);
+        assert_eq!(encoded, render_to_buffer(&grey, 1, 1, Some(DEFAULT_GAMMA)));
+        assert!(encoded[0] > linear[0] && encoded[1] > linear[1]);
+        // below -1 clamps to black before the power, rather than going NaN
+        assert_eq!(encoded[2], 0);
+    }
+
+    #[test]
+    fn test_render_ansi_pairs_rows_into_half_blocks() {
+        let ansi = render_ansi(&gradient(), 3, 5);
+        assert_eq!(ansi.matches('\n').count(), 3);
+        assert_eq!(ansi.matches('▀').count(), 9);
+        assert_eq!(ansi.matches("\x1b[38;2;").count(), 9);
+        // the odd fifth row has nothing below it
+        assert_eq!(ansi.matches("\x1b[48;2;").count(), 6);
+        assert_eq!(ansi.matches("\x1b[0m\n").count(), 3);
+        assert!(ansi.starts_with("\x1b[38;2;0;0;224m\x1b[48;2;0;136;224m▀"), "{:?}", ansi);
+    }
+}
@@ -1,20 +1,112 @@
+#[cfg(feature = "image")]
 use image::{ImageBuffer, RgbImage};
+use std::io::{self, Write};
+use std::ops::{Add, Mul};
+use std::sync::Arc;
+
+// canvas size used when a caller does not pick one, e.g. by the binary and `Canvas::default()`
+pub const DEFAULT_WIDTH: u32 = 400;
+pub const DEFAULT_HEIGHT: u32 = 400;
+
+// display gamma the 8-bit output is encoded for when a caller does not pick one, roughly sRGB
+pub const DEFAULT_GAMMA: f32 = 2.2;
 
 pub struct PixelCoordinates {
     pub x: f32,
     pub y: f32
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Colour {
     pub r: f32,
     pub g: f32,
     pub b: f32
 }
 
-pub fn render_pixels<F>(function: F, width: u32, height: u32) -> RgbImage 
+impl Colour {
+    // hue in degrees (any value, taken modulo 360), saturation and value in [0, 1] (clamped), as
+    // an rgb colour in the usual [-1, 1] channel range
+    pub fn from_hsv(hue: f32, saturation: f32, value: f32) -> Colour {
+        let (saturation, value) = (saturation.clamp(0.0, 1.0), value.clamp(0.0, 1.0));
+        let sector = hue.rem_euclid(360.0) / 60.0;
+        let chroma = value * saturation;
+        let second = chroma * (1.0 - (sector % 2.0 - 1.0).abs());
+        let (r, g, b) = match sector as u32 {
+            0 => (chroma, second, 0.0),
+            1 => (second, chroma, 0.0),
+            2 => (0.0, chroma, second),
+            3 => (0.0, second, chroma),
+            4 => (second, 0.0, chroma),
+            _ => (chroma, 0.0, second),
+        };
+        let lift = value - chroma;
+        let unit = |channel: f32| (channel + lift) * 2.0 - 1.0;
+        Colour { r: unit(r), g: unit(g), b: unit(b) }
+    }
+
+    // channel-wise comparison within `tol`, for asserting on rendered colours
+    pub fn approx_eq(&self, other: &Colour, tol: f32) -> bool {
+        (self.r - other.r).abs() <= tol &&
+        (self.g - other.g).abs() <= tol &&
+        (self.b - other.b).abs() <= tol
+    }
+
+    // every channel clamped into [-1, 1], with NaN channels read as 0.0
+    pub fn clamped(&self) -> Colour {
+        let channel = |c: f32| if c.is_nan() { 0.0 } else { c.clamp(-1.0, 1.0) };
+        Colour { r: channel(self.r), g: channel(self.g), b: channel(self.b) }
+    }
+
+    // `clamped`, then [-1, 1] onto 0..=255 linearly and rounded. the renderers quantise the same
+    // way when given Some(1.0)
+    pub fn to_rgb8(&self) -> [u8; 3] {
+        colour_to_rgb8(self, 1.0)
+    }
+
+    // channel-wise blend, `self` at t = 0 and `other` at t = 1. nothing is clamped, t included
+    pub fn lerp(&self, other: &Colour, t: f32) -> Colour {
+        *self * (1.0 - t) + *other * t
+    }
+
+    // shifts red up and blue down by `warmth` (the reverse when negative), leaving green alone.
+    // channels are not clamped, `render_pixels` does that when quantising
+    pub fn adjust_temperature(&self, warmth: f32) -> Colour {
+        Colour { r: self.r + warmth, g: self.g, b: self.b - warmth }
+    }
+}
+
+// channel-wise and unclamped, like the rest of the arithmetic on colours
+impl Add for Colour {
+    type Output = Colour;
+
+    fn add(self, other: Colour) -> Colour {
+        Colour { r: self.r + other.r, g: self.g + other.g, b: self.b + other.b }
+    }
+}
+
+impl Mul<f32> for Colour {
+    type Output = Colour;
+
+    fn mul(self, factor: f32) -> Colour {
+        Colour { r: self.r * factor, g: self.g * factor, b: self.b * factor }
+    }
+}
+
+// `Colour::adjust_temperature` over a whole rendered buffer, e.g. from `render::render_colours`
+pub fn adjust_buffer_temperature(colours: &mut [Colour], warmth: f32) {
+    for colour in colours {
+        *colour = colour.adjust_temperature(warmth);
+    }
+}
+
+// channels are encoded with `gamma` as in `render::render_to_buffer`, `DEFAULT_GAMMA` when None
+#[cfg(feature = "image")]
+pub fn render_pixels<F>(function: F, width: u32, height: u32, gamma: Option<f32>) -> RgbImage 
 where
     F: Fn(PixelCoordinates) -> Colour 
 {
+    let gamma = gamma.unwrap_or(DEFAULT_GAMMA);
     let mut img: RgbImage = ImageBuffer::new(width, height);
 
     for (px, py, pixel) in img.enumerate_pixels_mut() {
@@ -23,15 +115,56 @@ where
 
         let colour = function(PixelCoordinates { x, y });
 
-        let r = ((colour.r + 1.0) * 127.5).clamp(0.0, 255.0) as u8;
-        let g = ((colour.g + 1.0) * 127.5).clamp(0.0, 255.0) as u8;
-        let b = ((colour.b + 1.0) * 127.5).clamp(0.0, 255.0) as u8;
-
-        *pixel = image::Rgb([r, g, b]);
+        *pixel = image::Rgb(colour_to_rgb8(&colour, gamma));
     }
     img
 }
 
+// [-1, 1] onto 0..=255 per channel, gamma encoded: `Colour::clamped` (out-of-range values clamp
+// to the ends rather than wrap), normalised to [0, 1] and raised to 1 / `gamma`. a gamma of 1.0
+// is linear, as in `Colour::to_rgb8`
+pub(crate) fn colour_to_rgb8(colour: &Colour, gamma: f32) -> [u8; 3] {
+    let channel = |c: f32| ((c * 0.5 + 0.5).powf(1.0 / gamma) * 255.0).round() as u8;
+    let Colour { r, g, b } = colour.clamped();
+    [channel(r), channel(g), channel(b)]
+}
+
+// streams `frames` frames as YUV4MPEG2 (4:4:4, BT.601 limited range), e.g. for `ffmpeg -i -`.
+// `function` gets the frame's time `t` in [0, 1) alongside the pixel coordinates. `gamma` as in
+// `render_pixels`
+pub fn write_y4m<F, W>(function: F, width: u32, height: u32, frames: u32, fps: u32, gamma: Option<f32>, out: &mut W) -> io::Result<()>
+where
+    F: Fn(PixelCoordinates, f32) -> Colour,
+    W: Write,
+{
+    writeln!(out, "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444", width, height, fps)?;
+
+    let gamma = gamma.unwrap_or(DEFAULT_GAMMA);
+    let plane_size = (width * height) as usize;
+    let mut planes = vec![0u8; plane_size * 3];
+    for frame in 0..frames {
+        let t = frame as f32 / frames as f32;
+        for i in 0..plane_size {
+            // the same pixel mapping and quantisation as `render_pixels`
+            let (px, py) = (i as u32 % width, i as u32 / width);
+            let x = (px as f32 / (width - 1) as f32) * 2.0 - 1.0;
+            let y = (py as f32 / (height - 1) as f32) * 2.0 - 1.0;
+            let pixel = colour_to_rgb8(&function(PixelCoordinates { x, y }, t), gamma);
+            let [r, g, b] = pixel.map(|c| c as f32 / 255.0);
+            let y = 16.0 + 65.481 * r + 128.553 * g + 24.966 * b;
+            let u = 128.0 - 37.797 * r - 74.203 * g + 112.0 * b;
+            let v = 128.0 + 112.0 * r - 93.786 * g - 18.214 * b;
+            planes[i] = y.round() as u8;
+            planes[plane_size + i] = u.round() as u8;
+            planes[2 * plane_size + i] = v.round() as u8;
+        }
+
+        out.write_all(b"FRAME\n")?;
+        out.write_all(&planes)?;
+    }
+    Ok(())
+}
+
 pub fn fnv1a(input: &str) -> u64 {
     let mut hash: u64 = 0xcbf29ce484222325; 
     let prime: u64 = 0x100000001b3;
@@ -44,6 +177,67 @@ pub fn fnv1a(input: &str) -> u64 {
     hash
 }
 
+// deterministic pseudo-random value in [-1, 1] for an integer lattice point
+pub fn hash2d(ix: i32, iy: i32) -> f32 {
+    seeded_hash2d(ix, iy, 0)
+}
+
+// `hash2d` over a lattice picked by `seed`, seed 0 is the lattice `hash2d` uses
+pub fn seeded_hash2d(ix: i32, iy: i32, seed: u32) -> f32 {
+    let mut hash = (ix as u32).wrapping_mul(0x8da6b343) ^ (iy as u32).wrapping_mul(0xd8163841) ^ seed.wrapping_mul(0xcb1ab31f);
+    hash = (hash ^ (hash >> 13)).wrapping_mul(0x5bd1e995);
+    hash ^= hash >> 15;
+    (hash as f32 / u32::MAX as f32) * 2.0 - 1.0
+}
+
+// `hash2d` at the four surrounding lattice points, blended with a smoothstep. continuous, in [-1, 1],
+// with features about one unit apart
+pub fn value_noise(x: f32, y: f32) -> f32 {
+    seeded_value_noise(x, y, 0)
+}
+
+// `value_noise` over the `seeded_hash2d` lattice for `seed`
+pub fn seeded_value_noise(x: f32, y: f32, seed: u32) -> f32 {
+    let (x0, y0) = (x.floor(), y.floor());
+    let (ix, iy) = (x0 as i32, y0 as i32);
+    let fade = |t: f32| t * t * (3.0 - 2.0 * t);
+    let (tx, ty) = (fade(x - x0), fade(y - y0));
+
+    let hash = |ix: i32, iy: i32| seeded_hash2d(ix, iy, seed);
+    let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+    let top = lerp(hash(ix, iy), hash(ix.wrapping_add(1), iy), tx);
+    let bottom = lerp(hash(ix, iy.wrapping_add(1)), hash(ix.wrapping_add(1), iy.wrapping_add(1)), tx);
+    lerp(top, bottom, ty)
+}
+
+// `octaves` layers of `value_noise`, each at twice the frequency and half the amplitude of the
+// last, divided by the total amplitude so the sum stays in [-1, 1]
+pub fn turbulence(x: f32, y: f32, octaves: u8) -> f32 {
+    let (mut sum, mut total, mut frequency, mut amplitude) = (0.0, 0.0, 1.0, 1.0);
+    for _ in 0..octaves {
+        sum += amplitude * value_noise(x * frequency, y * frequency);
+        total += amplitude;
+        frequency *= 2.0;
+        amplitude *= 0.5;
+    }
+    if total > 0.0 { sum / total } else { 0.0 }
+}
+
+// a source of randomness for `Grammar`. only `next_float` is required; `next_range` falls back to
+// scaling a float, generators with integer output should override it. generation clones the rng
+// to rewind it when a branch runs out of depth
+pub trait Rng: Clone {
+    // uniform in [0, 1)
+    fn next_float(&mut self) -> f32;
+
+    // uniform in [min, max)
+    fn next_range(&mut self, min: u64, max: u64) -> u64 {
+        let offset = (self.next_float() * (max - min) as f32) as u64;
+        min + offset.min(max - min - 1)
+    }
+}
+
+#[derive(Clone)]
 pub struct LinearCongruentialGenerator {
     state: u64, 
     a: u64,    
@@ -53,14 +247,17 @@ pub struct LinearCongruentialGenerator {
 
 impl LinearCongruentialGenerator {
     pub fn new(seed: u64) -> Self {
-        Self {
-            state: seed,
-            a: 1664525,
-            c: 1013904223,
-            m: 2_u64.pow(32), 
-        }
+        Self::with_params(seed, 1664525, 1013904223, 2_u64.pow(32))
+    }
+
+    // state = (multiplier * state + increment) % modulus, with wrapping arithmetic, e.g. to match
+    // another implementation's constants. `modulus` must be non-zero
+    pub fn with_params(seed: u64, multiplier: u64, increment: u64, modulus: u64) -> Self {
+        assert!(modulus != 0, "an LCG modulus must be non-zero");
+        Self { state: seed, a: multiplier, c: increment, m: modulus }
     }
 
+    #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> u64 {
         self.state = (self.a.wrapping_mul(self.state).wrapping_add(self.c)) % self.m;
         self.state
@@ -73,4 +270,243 @@ impl LinearCongruentialGenerator {
     pub fn next_range(&mut self, min: u64, max: u64) -> u64 {
         min + (self.next() % (max - min))
     }
-}
\ No newline at end of file
+
+    // two draws as the high and low 32 bits, so all 64 bits vary with the default 2^32 modulus
+    pub fn next_u64(&mut self) -> u64 {
+        (self.next() << 32) | (self.next() & 0xffff_ffff)
+    }
+
+    // uniform in [lo, hi]. `next_float` can round up to exactly 1.0 in f32, so `hi` itself is possible
+    pub fn next_float_range(&mut self, lo: f32, hi: f32) -> f32 {
+        lo + self.next_float() * (hi - lo)
+    }
+
+    // uniform in 0..n. unlike `next_range`, draws from the top partial block of the modulus are
+    // thrown away and redrawn, so small values are not favoured. `n` can be at most the modulus,
+    // one draw has no more values than that
+    pub fn next_int(&mut self, n: usize) -> usize {
+        assert!(n > 0, "next_int needs a non-empty range");
+        let n = n as u64;
+        assert!(n <= self.m, "next_int range of {} exceeds the LCG modulus {}", n, self.m);
+        let limit = self.m - self.m % n;
+        loop {
+            let draw = self.next();
+            if draw < limit {
+                return (draw % n) as usize;
+            }
+        }
+    }
+}
+
+impl Rng for LinearCongruentialGenerator {
+    fn next_float(&mut self) -> f32 {
+        LinearCongruentialGenerator::next_float(self)
+    }
+
+    fn next_range(&mut self, min: u64, max: u64) -> u64 {
+        LinearCongruentialGenerator::next_range(self, min, max)
+    }
+}
+
+// Marsaglia's xorshift64 (shifts 13, 7, 17): full 64-bit state and no weak low bits, unlike the LCG
+#[derive(Clone)]
+pub struct Xorshift64 {
+    state: u64,
+}
+
+impl Xorshift64 {
+    // zero is the one state xorshift never leaves, so a zero seed is swapped for a fixed odd one
+    pub fn new(seed: u64) -> Self {
+        Self { state: if seed == 0 { 0x9e3779b97f4a7c15 } else { seed } }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    // the top 24 bits, so every value is exact in an f32 and stays below 1.0
+    pub fn next_float(&mut self) -> f32 {
+        (self.next() >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+impl Rng for Xorshift64 {
+    fn next_float(&mut self) -> f32 {
+        Xorshift64::next_float(self)
+    }
+
+    fn next_range(&mut self, min: u64, max: u64) -> u64 {
+        min + self.next() % (max - min)
+    }
+}
+
+// `rand`'s StdRng as a grammar's rng, for better statistical quality than the LCG at the cost of
+// the dependency. the same seed gives the same stream for a given version of `rand`
+#[cfg(feature = "rand")]
+#[derive(Clone)]
+pub struct StdRngSource(rand::rngs::StdRng);
+
+#[cfg(feature = "rand")]
+impl StdRngSource {
+    pub fn new(seed: u64) -> Self {
+        use rand::SeedableRng;
+        Self(rand::rngs::StdRng::seed_from_u64(seed))
+    }
+}
+
+#[cfg(feature = "rand")]
+impl Rng for StdRngSource {
+    fn next_float(&mut self) -> f32 {
+        rand::Rng::gen(&mut self.0)
+    }
+
+    fn next_range(&mut self, min: u64, max: u64) -> u64 {
+        rand::Rng::gen_range(&mut self.0, min..max)
+    }
+}
+
+// a fixed list of floats replayed in order (wrapping around at the end) as a grammar's
+// randomness, to script generation exactly. see `Grammar::with_float_sequence`
+#[derive(Clone)]
+pub struct RandomSource {
+    floats: Arc<[f32]>,
+    position: usize,
+}
+
+impl RandomSource {
+    pub fn sequence(floats: Vec<f32>) -> Self {
+        assert!(!floats.is_empty(), "a float sequence needs at least one value");
+        RandomSource { floats: floats.into(), position: 0 }
+    }
+}
+
+impl Rng for RandomSource {
+    fn next_float(&mut self) -> f32 {
+        let value = self.floats[self.position % self.floats.len()];
+        self.position += 1;
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn test_colour_approx_eq() {
+        let a = Colour { r: 0.0, g: 0.25, b: -0.5 };
+        let b = Colour { r: 1e-7, g: 0.25, b: -0.5 };
+        assert!(a.approx_eq(&b, 1e-6));
+        assert!(!a.approx_eq(&b, 1e-9));
+    }
+
+    #[test]
+    fn test_adjust_temperature() {
+        let colour = Colour { r: 0.2, g: -0.1, b: 0.4 };
+        let warmer = colour.adjust_temperature(0.25);
+        assert!(warmer.r > colour.r && warmer.b < colour.b);
+        assert_eq!(warmer.g, colour.g);
+        assert_eq!(colour.adjust_temperature(0.0), colour);
+
+        let mut buffer = vec![colour; 3];
+        adjust_buffer_temperature(&mut buffer, -0.25);
+        assert!(buffer.iter().all(|c| c.approx_eq(&Colour { r: -0.05, g: -0.1, b: 0.65 }, 1e-6)));
+    }
+
+    #[test]
+    fn test_write_y4m_header_and_frames() {
+        let mut out = Vec::new();
+        let function = |coords: PixelCoordinates, t: f32| Colour { r: coords.x, g: coords.y, b: t };
+        write_y4m(function, 4, 2, 2, 24, None, &mut out).unwrap();
+
+        let header = b"YUV4MPEG2 W4 H2 F24:1 Ip A1:1 C444\n";
+        assert!(out.starts_with(header));
+
+        let frame_size = b"FRAME\n".len() + 4 * 2 * 3;
+        assert_eq!(out.len(), header.len() + 2 * frame_size);
+        for frame in 0..2 {
+            let start = header.len() + frame * frame_size;
+            assert_eq!(&out[start..start + 6], b"FRAME\n");
+        }
+    }
+
+    #[test]
+    fn test_colour_to_rgb8_saturates() {
+        let colour = Colour { r: -1.0, g: 0.0, b: 1.0 };
+        assert_eq!(colour.to_rgb8(), [0, 128, 255]);
+        assert_eq!(Colour { r: f32::NAN, g: -3.0, b: 7.5 }.to_rgb8(), [128, 0, 255]);
+        assert_eq!(Colour { r: f32::NAN, g: -3.0, b: 0.25 }.clamped(), Colour { r: 0.0, g: -1.0, b: 0.25 });
+    }
+
+    #[test]
+    fn test_colour_arithmetic() {
+        let a = Colour { r: 0.1, g: -0.7, b: 0.9 };
+        let b = Colour { r: 0.6, g: 0.7, b: -2.0 };
+        assert!((a + b).approx_eq(&Colour { r: 0.7, g: 0.0, b: -1.1 }, 1e-6));
+        assert!((a * 3.0).approx_eq(&Colour { r: 0.3, g: -2.1, b: 2.7 }, 1e-6));
+        assert_eq!(a.lerp(&b, 0.0), a);
+        assert_eq!(a.lerp(&b, 1.0), b);
+        assert!(a.lerp(&b, 0.5).approx_eq(&Colour { r: 0.35, g: 0.0, b: -0.55 }, 1e-6));
+        // unclamped, past the far end
+        assert!(a.lerp(&b, 2.0).approx_eq(&Colour { r: 1.1, g: 2.1, b: -4.9 }, 1e-5));
+    }
+
+    #[test]
+    fn test_lcg_with_default_params_matches_new() {
+        let mut default = LinearCongruentialGenerator::new(fnv1a("lcg"));
+        let mut explicit = LinearCongruentialGenerator::with_params(fnv1a("lcg"), 1664525, 1013904223, 1 << 32);
+        for _ in 0..1000 {
+            assert_eq!(default.next_float().to_bits(), explicit.next_float().to_bits());
+            assert_eq!(default.next_range(3, 17), explicit.next_range(3, 17));
+        }
+
+        // a tiny generator cycles through its whole modulus
+        let mut tiny = LinearCongruentialGenerator::with_params(0, 5, 3, 16);
+        let states: Vec<u64> = (0..16).map(|_| tiny.next()).collect();
+        assert_eq!(states.iter().collect::<BTreeSet<_>>().len(), 16);
+    }
+
+    #[test]
+    fn test_lcg_integer_and_ranged_draws() {
+        let mut rng = LinearCongruentialGenerator::new(fnv1a("ranges"));
+        let mut seen = [false; 7];
+        for _ in 0..10_000 {
+            let value = rng.next_float_range(-2.5, 4.0);
+            assert!((-2.5..=4.0).contains(&value), "{}", value);
+            seen[rng.next_int(7)] = true;
+            assert_eq!(rng.next_int(1), 0);
+        }
+        assert!(seen.iter().all(|&hit| hit));
+        assert!((0..100).any(|_| rng.next_u64() > u32::MAX as u64));
+
+        // with a modulus of 10, draws of 9 would favour 0 for n = 3 and are redrawn instead
+        let mut tiny = LinearCongruentialGenerator::with_params(0, 1, 1, 10);
+        let draws: Vec<usize> = (0..9).map(|_| tiny.next_int(3)).collect();
+        assert_eq!(draws, vec![1, 2, 0, 1, 2, 0, 1, 2, 0]);
+        assert!((0..20).all(|_| tiny.next_int(10) < 10));
+    }
+
+    #[test]
+    #[should_panic(expected = "next_int range of 20 exceeds the LCG modulus 10")]
+    fn test_lcg_next_int_rejects_ranges_past_the_modulus() {
+        LinearCongruentialGenerator::with_params(0, 1, 1, 10).next_int(20);
+    }
+
+    #[test]
+    fn test_xorshift_is_deterministic_and_centred() {
+        let mut rng = Xorshift64::new(fnv1a("xorshift"));
+        let draws: Vec<f32> = (0..10_000).map(|_| rng.next_float()).collect();
+        assert!(draws.iter().all(|draw| (0.0..1.0).contains(draw)));
+        let mean = draws.iter().sum::<f32>() / draws.len() as f32;
+        assert!((mean - 0.5).abs() < 0.01, "{}", mean);
+
+        let mut again = Xorshift64::new(fnv1a("xorshift"));
+        assert!(draws.iter().all(|draw| again.next_float() == *draw));
+        assert_ne!(Xorshift64::new(0).next(), 0);
+    }
+}
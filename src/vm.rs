@@ -0,0 +1,278 @@
+// a tree flattened into postfix opcodes over a value stack, so rendering walks one contiguous
+// `Vec` per pixel instead of chasing boxes. operands are pushed left to right and each operator
+// pops its arguments and pushes its result. `If` becomes a conditional jump over the branch not
+// taken, like `eval` which only evaluates one side. both `compile` and `eval` use explicit stacks,
+// so arbitrarily deep trees neither recurse nor overflow
+use crate::{clamp, div, ln, max, min, mix, mix_unbounded, modulo, pow, remap, smoothmin, smoothstep, tan, Node, WaveKind, MAX_TURBULENCE_OCTAVES};
+use crate::utils::{seeded_value_noise, turbulence};
+
+#[derive(Clone, Copy, Debug)]
+enum Op {
+    X,
+    Y,
+    Radius,
+    Constant(f32),
+    Unary(fn(f32) -> f32),
+    Binary(fn(f32, f32) -> f32),
+    Ternary(fn(f32, f32, f32) -> f32),
+    Quaternary(fn(f32, f32, f32, f32) -> f32),
+    Remap,
+    Turbulence(u8),
+    Noise(u32),
+    JumpUnlessPositive(usize),  // pops the condition, jumps to the op index when it is not > 0
+    Jump(usize),
+}
+
+impl Op {
+    // the operator for a node with its children already on the stack
+    fn for_node(node: &Node) -> Op {
+        match node {
+            Node::X => Op::X,
+            Node::Y => Op::Y,
+            Node::Radius => Op::Radius,
+            Node::Number(value) => Op::Constant(*value),
+            Node::Boolean(value) => Op::Constant(if *value { 1.0 } else { 0.0 }),
+            Node::Add(_, _) => Op::Binary(|lhs, rhs| (lhs + rhs)/2.0),
+            Node::Sub(_, _) => Op::Binary(|lhs, rhs| lhs - rhs),
+            Node::Min(_, _) => Op::Binary(min),
+            Node::Max(_, _) => Op::Binary(max),
+            Node::Mult(_, _) => Op::Binary(|lhs, rhs| lhs * rhs),
+            Node::Div(_, _) => Op::Binary(div),
+            Node::Modulo(_, _) => Op::Binary(modulo),
+            Node::Pow(_, _) => Op::Binary(pow),
+            Node::Gt(_, _) => Op::Binary(|lhs, rhs| if lhs - rhs > 0.0 { 1.0 } else { 0.0 }),
+            Node::Step(_, _) => Op::Binary(|edge, value| if value >= edge { 1.0 } else { 0.0 }),
+            Node::Sin(_) => Op::Unary(f32::sin),
+            Node::Cos(_) => Op::Unary(f32::cos),
+            Node::Exp(_) => Op::Unary(f32::exp),
+            Node::Sigmoid(_) => Op::Unary(|val| 1.0 / (1.0 + (-val).exp())),
+            Node::Tan(_) => Op::Unary(tan),
+            Node::Abs(_) => Op::Unary(f32::abs),
+            Node::Log(_) => Op::Unary(ln),
+            Node::Floor(_) => Op::Unary(f32::floor),
+            Node::Ceil(_) => Op::Unary(f32::ceil),
+            Node::Round(_) => Op::Unary(f32::round),
+            Node::Fract(_) => Op::Unary(|val| val - val.floor()),
+            Node::Sqrt(_) => Op::Unary(|val| val.sqrt().max(0.0)),
+            Node::Sign(_) => Op::Unary(|val| if val > 0.0 { 1.0 } else if val < 0.0 { -1.0 } else { 0.0 }),
+            Node::Wrap(_) => Op::Unary(|val| (2.0 * std::f32::consts::PI * (val - val.floor())).sin()),
+            Node::Clamp(_, _, _) => Op::Ternary(clamp),
+            Node::Smoothstep(_, _, _) => Op::Ternary(smoothstep),
+            Node::Lerp(_, _, _) => Op::Ternary(|a, b, t| a + (b - a) * t),
+            Node::Smoothmin(_, _, _) => Op::Ternary(smoothmin),
+            Node::Wave(_, _, _, WaveKind::Sin) => Op::Ternary(|input, frequency, phase| WaveKind::Sin.apply(frequency * input + phase)),
+            Node::Wave(_, _, _, WaveKind::Cos) => Op::Ternary(|input, frequency, phase| WaveKind::Cos.apply(frequency * input + phase)),
+            Node::Wave(_, _, _, WaveKind::Tan) => Op::Ternary(|input, frequency, phase| WaveKind::Tan.apply(frequency * input + phase)),
+            Node::Mix(_, _, _, _) => Op::Quaternary(mix),
+            Node::MixUnbounded(_, _, _, _) => Op::Quaternary(mix_unbounded),
+            Node::Remap(_, _, _, _, _) => Op::Remap,
+            Node::Turbulence(_, _, octaves) => Op::Turbulence((*octaves).min(MAX_TURBULENCE_OCTAVES)),
+            Node::Noise(_, _, seed) => Op::Noise(*seed),
+            Node::If(_, _, _) | Node::Random | Node::Rule(_) | Node::Triple(_, _, _) | Node::Quad(_, _, _, _) | Node::Palette(_, _) => {
+                panic!("unexpected Node kind during compile: {:?}", node)
+            }
+        }
+    }
+}
+
+enum Task<'a> {
+    Visit(&'a Node),
+    Emit(Op),
+    // the condition of an If is on the stack, jump past the then branch when it fails
+    SkipThen,
+    // the then branch is done: jump past the else branch, which starts here
+    SkipElse,
+    // the else branch is done, the If ends here
+    EndIf,
+}
+
+#[derive(Clone, Debug)]
+pub struct Program {
+    ops: Vec<Op>,
+    max_stack: usize,
+}
+
+impl Program {
+    // panics on nodes `eval` panics on (Random, Rule, Triple, Quad, Palette)
+    pub fn compile(node: &Node) -> Program {
+        let mut ops = Vec::new();
+        let (mut height, mut max_stack) = (0usize, 0usize);
+        // indices of jumps whose target is not known yet
+        let mut pending = Vec::new();
+        let mut tasks = vec![Task::Visit(node)];
+
+        while let Some(task) = tasks.pop() {
+            match task {
+                Task::Visit(Node::If(cond, then, elze)) => {
+                    tasks.extend([Task::EndIf, Task::Visit(elze), Task::SkipElse, Task::Visit(then), Task::SkipThen, Task::Visit(cond)]);
+                }
+                Task::Visit(node) => {
+                    tasks.push(Task::Emit(Op::for_node(node)));
+                    tasks.extend(node.children().into_iter().rev().map(Task::Visit));
+                }
+                Task::Emit(op) => {
+                    let popped = match op {
+                        Op::X | Op::Y | Op::Radius | Op::Constant(_) => 0,
+                        Op::Unary(_) => 1,
+                        Op::Binary(_) | Op::Turbulence(_) | Op::Noise(_) => 2,
+                        Op::Ternary(_) => 3,
+                        Op::Quaternary(_) => 4,
+                        Op::Remap => 5,
+                        Op::JumpUnlessPositive(_) | Op::Jump(_) => unreachable!("jumps are emitted by the If tasks"),
+                    };
+                    height = height - popped + 1;
+                    max_stack = max_stack.max(height);
+                    ops.push(op);
+                }
+                Task::SkipThen => {
+                    height -= 1;
+                    pending.push(ops.len());
+                    ops.push(Op::JumpUnlessPositive(0));
+                }
+                Task::SkipElse => {
+                    // the else branch starts from the height the then branch started from
+                    height -= 1;
+                    let skip_then = pending.pop().expect("SkipThen comes first");
+                    ops[skip_then] = Op::JumpUnlessPositive(ops.len() + 1);
+                    pending.push(ops.len());
+                    ops.push(Op::Jump(0));
+                }
+                Task::EndIf => {
+                    let skip_else = pending.pop().expect("SkipElse comes first");
+                    ops[skip_else] = Op::Jump(ops.len());
+                }
+            }
+        }
+        Program { ops, max_stack }
+    }
+
+    // the same value `eval` gives for the compiled node, bit for bit
+    pub fn eval(&self, x: f32, y: f32) -> f32 {
+        let mut stack: Vec<f32> = Vec::with_capacity(self.max_stack);
+        let mut pc = 0;
+        while pc < self.ops.len() {
+            let op = self.ops[pc];
+            pc += 1;
+            let value = match op {
+                Op::X => x,
+                Op::Y => y,
+                Op::Radius => (x * x + y * y).sqrt(),
+                Op::Constant(value) => value,
+                Op::Unary(f) => f(stack.pop().expect("a unary op has its operand on the stack")),
+                Op::Binary(f) => {
+                    let base = stack.len() - 2;
+                    let value = f(stack[base], stack[base + 1]);
+                    stack.truncate(base);
+                    value
+                }
+                Op::Turbulence(octaves) => {
+                    let base = stack.len() - 2;
+                    let value = turbulence(stack[base], stack[base + 1], octaves);
+                    stack.truncate(base);
+                    value
+                }
+                Op::Noise(seed) => {
+                    let base = stack.len() - 2;
+                    let value = seeded_value_noise(stack[base], stack[base + 1], seed);
+                    stack.truncate(base);
+                    value
+                }
+                Op::Ternary(f) => {
+                    let base = stack.len() - 3;
+                    let value = f(stack[base], stack[base + 1], stack[base + 2]);
+                    stack.truncate(base);
+                    value
+                }
+                Op::Quaternary(f) => {
+                    let base = stack.len() - 4;
+                    let value = f(stack[base], stack[base + 1], stack[base + 2], stack[base + 3]);
+                    stack.truncate(base);
+                    value
+                }
+                Op::Remap => {
+                    let base = stack.len() - 5;
+                    let (value, in_lo, in_hi) = (stack[base], stack[base + 1], stack[base + 2]);
+                    let (out_lo, out_hi) = (stack[base + 3], stack[base + 4]);
+                    stack.truncate(base);
+                    remap(value, in_lo, in_hi, out_lo, out_hi)
+                }
+                Op::JumpUnlessPositive(target) => {
+                    // NaN fails the test and takes the else branch, like `eval`
+                    let cond = stack.pop().expect("an If condition is on the stack");
+                    if cond <= 0.0 || cond.is_nan() {
+                        pc = target;
+                    }
+                    continue;
+                }
+                Op::Jump(target) => {
+                    pc = target;
+                    continue;
+                }
+            };
+            stack.push(value);
+        }
+        stack.pop().expect("a compiled program leaves its result on the stack")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Grammar;
+    use crate::utils::fnv1a;
+
+    #[test]
+    fn test_program_matches_eval_over_a_grid() {
+        for (name, depth) in [("vm", 20), ("bytecode", 20), ("spiderman", 40)] {
+            let Node::Triple(r, g, b) = *Grammar::default(fnv1a(name)).gen_rule(0, depth).unwrap() else {
+                panic!("the Entry rule makes a Triple");
+            };
+            for channel in [r, g, b] {
+                let program = Program::compile(&channel);
+                for i in 0..=64 {
+                    for j in 0..=64 {
+                        let (x, y) = (-1.0 + i as f32 / 32.0, -1.0 + j as f32 / 32.0);
+                        assert_eq!(program.eval(x, y).to_bits(), channel.eval(x, y).to_bits(), "{:?} at ({}, {})", channel, x, y);
+                    }
+                }
+            }
+        }
+
+        // nested Ifs, both branches reachable across the grid
+        let branchy = Node::If(
+            Box::new(Node::Gt(Box::new(Node::X), Box::new(Node::Y))),
+            Box::new(Node::If(Box::new(Node::Y), Box::new(Node::Number(1.0)), Box::new(Node::Sin(Box::new(Node::X))))),
+            Box::new(Node::Add(Box::new(Node::If(Box::new(Node::X), Box::new(Node::Y), Box::new(Node::Number(-1.0)))), Box::new(Node::Radius))),
+        );
+        let program = Program::compile(&branchy);
+        for i in 0..=20 {
+            for j in 0..=20 {
+                let (x, y) = (-1.0 + i as f32 * 0.1, -1.0 + j as f32 * 0.1);
+                assert_eq!(program.eval(x, y).to_bits(), branchy.eval(x, y).to_bits());
+            }
+        }
+    }
+
+    #[test]
+    fn test_program_handles_deep_trees_without_recursion() {
+        // deep enough that recursing per node would need far more stack than flattening does
+        let mut chain = Node::X;
+        for _ in 0..5_000 {
+            chain = Node::Abs(Box::new(Node::Sub(Box::new(chain), Box::new(Node::Number(0.001)))));
+        }
+        let program = Program::compile(&chain);
+        assert_eq!(program.ops.len(), 5_000 * 3 + 1);
+
+        let mut expected = 0.3f32;
+        for _ in 0..5_000 {
+            expected = (expected - 0.001).abs();
+        }
+        assert_eq!(program.eval(0.3, 0.0), expected);
+
+        // dropping the boxed chain recursively is its own problem, so unwind it by hand
+        while let Node::Abs(inner) = chain {
+            let Node::Sub(lhs, _) = *inner else { unreachable!() };
+            chain = *lhs;
+        }
+    }
+}